@@ -0,0 +1,15 @@
+#import bevy_pbr::mesh_view_bindings::lights
+
+// Share of the light that still reaches surfaces facing away from the sun.
+const AMBIENT = 0.3;
+
+// Brightness of a surface facing `normal`, lit from the direction of the
+// scene's directional light so it matches the standard materials.
+fn sun_light(normal: vec3<f32>) -> f32 {
+    if lights.n_directional_lights == 0u {
+        return AMBIENT;
+    }
+
+    let sun = lights.directional_lights[0].direction_to_light;
+    return AMBIENT + (1.0 - AMBIENT) * max(dot(normal, sun), 0.0);
+}
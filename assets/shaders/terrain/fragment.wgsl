@@ -1,30 +1,61 @@
+#import "shaders/lighting.wgsl"::sun_light
+
 struct TerrainMaterial {
-    @location(0) color: vec4<f32>,
+    sea_level: f32,
+    sand_height: f32,
+    snow_height: f32,
+    rock_slope: f32,
+    height_blend: f32,
+    slope_blend: f32,
+    // Center in xyz and radius in w in planet mode, zero radius on the flat world.
+    planet: vec4<f32>,
 };
 
 @group(2) @binding(0)
 var<uniform> material: TerrainMaterial;
 
-// Function to map a height value to a color
-fn colorFromHeight(height: f32) -> vec4<f32> {
-    // Example gradient mapping, you can customize this
-    if height < 0.3 {
-        return vec4<f32>(0.0, 0.5, 1.0, 1.0); // Blue for low heights (water)
-    } else if height < 0.6 {
-        return vec4<f32>(0.8, 0.8, 0.8, 1.0); // Gray for mid-heights (rocky)
-    } else {
-        return vec4<f32>(1.0, 1.0, 1.0, 1.0); // White for high heights (snowy)
-    }
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+};
+
+const WATER = vec3<f32>(0.06, 0.24, 0.45);
+const SAND = vec3<f32>(0.76, 0.70, 0.50);
+const GRASS = vec3<f32>(0.25, 0.45, 0.15);
+const ROCK = vec3<f32>(0.40, 0.37, 0.35);
+const SNOW = vec3<f32>(0.95, 0.95, 0.97);
+
+// 0 below `threshold`, 1 above it, with a smooth transition `width` wide.
+fn step_over(threshold: f32, width: f32, value: f32) -> f32 {
+    return smoothstep(threshold - width * 0.5, threshold + width * 0.5, value);
 }
 
 @fragment
-fn fragment() -> @location(0) vec4<f32> {
-    // You need to pass the height value from your vertex shader
-    // For example, you could pass it as another attribute like this:
-    // var<in> height: f32;
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let position = in.world_position.xyz;
+    let normal = normalize(in.world_normal);
+
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    var height = position.y;
+    if material.planet.w > 0.0 {
+        let radial = position - material.planet.xyz;
+        up = normalize(radial);
+        height = length(radial) - material.planet.w;
+    }
+
+    // 0 on level ground, 1 on a vertical cliff.
+    let slope = 1.0 - max(dot(normal, up), 0.0);
+
+    let steep = step_over(material.rock_slope, material.slope_blend, slope);
+
+    var color = SAND;
+    color = mix(color, GRASS, step_over(material.sea_level + material.sand_height, material.height_blend, height));
+    color = mix(color, ROCK, steep);
+    color = mix(color, SNOW, step_over(material.snow_height, material.height_blend, height) * (1.0 - steep));
+    color = mix(color, WATER, 1.0 - step_over(material.sea_level, material.height_blend * 0.25, height));
 
-    // Then calculate the color based on the height value
-    let color = colorFromHeight(material.color.r); // Assuming red channel of color represents height
+    let light = sun_light(normal);
 
-    return vec4<f32>(color);
+    return vec4<f32>(color * light, 1.0);
 }
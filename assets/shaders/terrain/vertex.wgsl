@@ -1,43 +1,28 @@
-#import bevy_pbr::mesh_view_bindings
-#import bevy_pbr::mesh_bindings
-
-#import bevy_pbr::mesh_functions
+#import bevy_pbr::{
+    mesh_functions::{get_model_matrix, mesh_normal_local_to_world, mesh_position_local_to_world},
+    view_transformations::position_world_to_clip,
+}
 
 struct Vertex {
+    @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
-#ifdef VERTEX_UVS
-    @location(2) uv: vec2<f32>,
-#endif
-#ifdef VERTEX_TANGENTS
-    @location(3) tangent: vec4<f32>,
-#endif
-#ifdef VERTEX_COLORS
-    @location(4) color: vec4<f32>,
-#endif
 };
 
-struct TerrainMaterial {
-    @location(0) heights: f32,
-}
-
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_position: vec4<f32>,
     @location(1) world_normal: vec3<f32>,
 };
 
-@group(2) @binding(0)
-var<uniform> material: TerrainMaterial;
-
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
-    var output: VertexOutput;
+    var out: VertexOutput;
 
-    var model = mesh.model;
-    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+    let model = get_model_matrix(vertex.instance_index);
+    out.world_normal = mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
     out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
-    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    out.clip_position = position_world_to_clip(out.world_position.xyz);
 
-    return output;
+    return out;
 }
@@ -0,0 +1,47 @@
+#import bevy_pbr::view_transformations::position_world_to_clip
+#import "shaders/lighting.wgsl"::sun_light
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(5) color: vec4<f32>,
+    // World position and scale.
+    @location(8) i_position_scale: vec4<f32>,
+    @location(9) i_rotation: vec4<f32>,
+    @location(10) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let offset = rotate(vertex.i_rotation, vertex.position * vertex.i_position_scale.w);
+
+    var out: VertexOutput;
+    out.clip_position = position_world_to_clip(vertex.i_position_scale.xyz + offset);
+    out.world_normal = rotate(vertex.i_rotation, vertex.normal);
+    // The vertex alpha says how much of the instance's tint it takes, so
+    // trunks keep their own colour.
+    out.color = vertex.color.rgb * mix(vec3<f32>(1.0), vertex.i_color.rgb, vertex.color.a);
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    var normal = normalize(in.world_normal);
+    if !front_facing {
+        normal = -normal;
+    }
+
+    let light = sun_light(normal);
+    return vec4<f32>(in.color * light, 1.0);
+}
@@ -0,0 +1,45 @@
+#import bevy_pbr::mesh_view_bindings::view
+#import "shaders/lighting.wgsl"::sun_light
+
+struct WaterMaterial {
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    depth_range: f32,
+    shore_fade: f32,
+};
+
+@group(2) @binding(0)
+var<uniform> material: WaterMaterial;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) depth: f32,
+};
+
+const FOAM = vec3<f32>(0.9, 0.95, 1.0);
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Above the ground the sheet is hidden under the terrain anyway.
+    if in.depth <= 0.0 {
+        discard;
+    }
+
+    let normal = normalize(in.world_normal);
+    let to_camera = normalize(view.world_position - in.world_position.xyz);
+
+    let deep = clamp(in.depth / material.depth_range, 0.0, 1.0);
+    var color = mix(material.shallow_color, material.deep_color, sqrt(deep));
+
+    // A band of foam along the shore, fading the water in behind it.
+    let shore = clamp(in.depth / max(material.shore_fade, 0.001), 0.0, 1.0);
+    color = vec4<f32>(mix(FOAM, color.rgb, shore), color.a * mix(0.6, 1.0, shore));
+
+    // Grazing angles reflect more of the sky.
+    let fresnel = pow(1.0 - max(dot(normal, to_camera), 0.0), 5.0);
+    let light = sun_light(normal);
+
+    return vec4<f32>(color.rgb * light + fresnel * 0.3, mix(color.a, 1.0, fresnel));
+}
@@ -0,0 +1,31 @@
+#import bevy_pbr::{
+    mesh_functions::{get_model_matrix, mesh_normal_local_to_world, mesh_position_local_to_world},
+    view_transformations::position_world_to_clip,
+}
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) depth: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) depth: f32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let model = get_model_matrix(vertex.instance_index);
+    out.world_normal = mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+    out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
+    out.clip_position = position_world_to_clip(out.world_position.xyz);
+    out.depth = vertex.depth;
+
+    return out;
+}
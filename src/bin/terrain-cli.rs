@@ -0,0 +1,228 @@
+//! Runs the terrain generation pipeline without a window or GPU, for batch
+//! exports and regression artefacts.
+
+use std::{fs, path::PathBuf, process::ExitCode, sync::Arc};
+
+use bevy::prelude::*;
+use planetgame_rs::terrain::{
+    export::{
+        export_heightmap, export_meshes, export_rivers, generate_region_mesh, terrain_stats,
+        HeightmapExport, HeightmapFormat, MeshFormat,
+    },
+    generation::{build_terrain_sources, BiomeMap},
+    resources::GenerationSettings,
+};
+
+const USAGE: &str = "\
+Usage: terrain-cli <heightmap|mesh|stats|rivers> [options]
+
+Options:
+    --seed <u32>                 Override the seed from the settings
+    --settings <file.ron>        GenerationSettings; missing fields use defaults
+    --region <x0,z0,x1,z1>       World rectangle to bake (default 0,0,4096,4096)
+    --resolution <n>             Samples per side, or quads per side for meshes (default 1024)
+    --format <png|raw|exr>       Heightmap format (default png)
+    --output <path>              Output file; stats and rivers go to stdout without one,
+                                 meshes are written as glTF for .glb and as OBJ otherwise
+
+Rivers are traced over --region with a drainage grid of --resolution samples
+per side, whether or not the settings enable them.";
+
+enum Command {
+    Heightmap,
+    Mesh,
+    Stats,
+    Rivers,
+}
+
+struct Options {
+    command: Command,
+    seed: Option<u32>,
+    settings: Option<PathBuf>,
+    region: Rect,
+    resolution: u32,
+    format: HeightmapFormat,
+    output: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let command = match args.next().as_deref() {
+        Some("heightmap") => Command::Heightmap,
+        Some("mesh") => Command::Mesh,
+        Some("stats") => Command::Stats,
+        Some("rivers") => Command::Rivers,
+        Some(other) => return Err(format!("unknown command `{other}`")),
+        None => return Err("missing command".into()),
+    };
+
+    let mut options = Options {
+        command,
+        seed: None,
+        settings: None,
+        region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+        resolution: 1024,
+        format: HeightmapFormat::Png16,
+        output: None,
+    };
+
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {flag}"))
+        };
+
+        match flag.as_str() {
+            "--seed" => {
+                options.seed = Some(value()?.parse().map_err(|err| format!("--seed: {err}"))?)
+            }
+            "--settings" => options.settings = Some(value()?.into()),
+            "--region" => {
+                let corners: Vec<f32> = value()?
+                    .split(',')
+                    .map(|v| v.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|err| format!("--region: {err}"))?;
+                let [x0, z0, x1, z1] = corners[..] else {
+                    return Err("--region needs four values".into());
+                };
+                options.region = Rect::new(x0, z0, x1, z1);
+            }
+            "--resolution" => {
+                options.resolution = value()?
+                    .parse()
+                    .map_err(|err| format!("--resolution: {err}"))?
+            }
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "png" => HeightmapFormat::Png16,
+                    "raw" => HeightmapFormat::RawF32,
+                    "exr" => HeightmapFormat::Exr,
+                    other => return Err(format!("unknown format `{other}`")),
+                }
+            }
+            "--output" => options.output = Some(value()?.into()),
+            other => return Err(format!("unknown option `{other}`")),
+        }
+    }
+
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut settings = match &options.settings {
+        Some(path) => {
+            let source =
+                fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+            ron::from_str::<GenerationSettings>(&source)
+                .map_err(|err| format!("{}: {err}", path.display()))?
+        }
+        None => GenerationSettings::default(),
+    };
+
+    if let Some(seed) = options.seed {
+        settings.seed = seed;
+    }
+
+    if let Command::Rivers = options.command {
+        settings.rivers.enabled = true;
+        settings.rivers.region = options.region;
+        settings.rivers.resolution = options.resolution;
+    }
+
+    let sources = build_terrain_sources(&settings);
+    let source = sources.height;
+    let biomes = Arc::new(BiomeMap::new(&settings).with_lakes(sources.lakes.clone()));
+
+    match options.command {
+        Command::Heightmap => {
+            let export = HeightmapExport {
+                path: options.output.unwrap_or_else(|| {
+                    PathBuf::from("heightmap").with_extension(options.format.extension())
+                }),
+                region: options.region,
+                resolution: UVec2::splat(options.resolution),
+                format: options.format,
+            };
+
+            let metadata = export_heightmap(source.as_ref(), &export)
+                .map_err(|err| format!("{}: {err}", export.path.display()))?;
+            println!(
+                "wrote {}x{} heightmap to {} (heights {} to {})",
+                metadata.width,
+                metadata.height,
+                export.path.display(),
+                metadata.min_height,
+                metadata.max_height
+            );
+        }
+        Command::Mesh => {
+            let path = options.output.unwrap_or_else(|| "terrain.obj".into());
+            let (mesh, transform) =
+                generate_region_mesh(source, biomes, options.region, options.resolution);
+
+            let format = MeshFormat::from_path(&path).unwrap_or(MeshFormat::Obj);
+
+            export_meshes([(&mesh, GlobalTransform::from(transform))], format, &path)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            println!("wrote mesh to {}", path.display());
+        }
+        Command::Stats => {
+            let stats = terrain_stats(
+                source.as_ref(),
+                &biomes,
+                options.region,
+                UVec2::splat(options.resolution),
+            );
+            let stats = ron::ser::to_string_pretty(&stats, ron::ser::PrettyConfig::default())
+                .map_err(|err| err.to_string())?;
+
+            match options.output {
+                Some(path) => {
+                    fs::write(&path, stats).map_err(|err| format!("{}: {err}", path.display()))?
+                }
+                None => println!("{stats}"),
+            }
+        }
+        Command::Rivers => match options.output {
+            Some(path) => {
+                export_rivers(&sources.rivers, &path)
+                    .map_err(|err| format!("{}: {err}", path.display()))?;
+                println!(
+                    "wrote {} rivers to {}",
+                    sources.rivers.rivers.len(),
+                    path.display()
+                );
+            }
+            None => println!(
+                "{}",
+                ron::ser::to_string_pretty(
+                    sources.rivers.as_ref(),
+                    ron::ser::PrettyConfig::default()
+                )
+                .map_err(|err| err.to_string())?
+            ),
+        },
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -15,13 +15,11 @@ use bevy_xpbd_3d::{
         PhysicsPlugins,
     },
 };
-use diagnostics::DiagnosticsPlugin;
-use spectator::{components::SpectatorCamera, SpectatorPlugin};
-use terrain::TerrainPlugin;
-
-mod diagnostics;
-mod spectator;
-mod terrain;
+use planetgame_rs::{
+    diagnostics::DiagnosticsPlugin,
+    spectator::{components::SpectatorCamera, SpectatorPlugin},
+    terrain::{components::PhysicsActor, TerrainPlugin},
+};
 
 fn main() {
     App::new()
@@ -77,6 +75,7 @@ fn start(mut window: Query<&mut Window, With<PrimaryWindow>>, mut commands: Comm
             ..Default::default()
         },
         SpectatorCamera,
+        PhysicsActor,
         RigidBody::Kinematic,
         Collider::default(),
         Sensor,
@@ -1,10 +1,70 @@
-use bevy::{prelude::*, tasks::Task};
+use bevy::{prelude::*, render::primitives::Aabb, tasks::Task};
+
+use super::planet::CubeFace;
 
 #[derive(Component)]
 pub struct TerrainChunk(pub Handle<Mesh>, pub Vec2);
 
+/// Where a chunk sits in its LOD tree.
+#[derive(Component, Clone, Copy)]
+pub struct ChunkLod {
+    pub boundary: Rect,
+    /// Cube face the chunk lies on in planet mode.
+    pub face: Option<CubeFace>,
+    pub depth: usize,
+}
+
+/// Sent whenever a chunk's mesh has been generated, both when the chunk is
+/// first loaded and when it is rebuilt.
+#[derive(Event)]
+pub struct ChunkMeshReady {
+    pub chunk: Entity,
+}
+
 #[derive(Component)]
 pub struct PendingTerrainChunk(pub Task<Mesh>, pub Vec2);
 
 #[derive(Component)]
 pub struct DeletedTerrainChunk;
+
+/// Blends a chunk between its own vertices and [`crate::terrain::generation::ATTRIBUTE_MORPH_POSITION`]
+/// depending on how close the player is to the distance at which it gets merged.
+#[derive(Component, Default)]
+pub struct Geomorph {
+    /// Unmorphed vertex positions and normals, filled in once the mesh is
+    /// generated.
+    pub base: Vec<[f32; 3]>,
+    pub base_normals: Vec<[f32; 3]>,
+    /// Depth of the parent node.
+    pub depth: usize,
+    pub parent_size: f32,
+    /// Lowest and highest morph factor over the chunk when its mesh was last
+    /// morphed, `None` until then.
+    pub applied: Option<Vec2>,
+    /// The chunk's collider when it was last built for the morph, and the
+    /// factors it was built at.
+    pub collider: Option<(Entity, Vec2)>,
+}
+
+/// Entities that terrain chunks within [`crate::terrain::resources::ColliderSettings::radius`]
+/// get colliders for.
+#[derive(Component)]
+pub struct PhysicsActor;
+
+/// Added to every chunk once its mesh is ready.
+#[derive(Component)]
+pub struct ChunkCollider {
+    /// World space bounds of the chunk's mesh, however far it is morphed.
+    pub bounds: Aabb,
+    /// Child entity holding the static collider, while the chunk has one.
+    pub collider: Option<Entity>,
+}
+
+/// Highest point of a chunk's mesh, as a height above the planet's surface in
+/// planet mode.
+#[derive(Component)]
+pub struct ChunkPeak(pub f32);
+
+/// Marks chunks that lie entirely below [`crate::terrain::resources::TerrainSettings::sea_level`].
+#[derive(Component)]
+pub struct Underwater;
@@ -0,0 +1,138 @@
+use std::{fs, path::PathBuf};
+
+use bevy::prelude::*;
+use image::{ImageBuffer, ImageFormat, Luma, Rgb};
+use serde::{Deserialize, Serialize};
+
+use crate::terrain::generation::{sample_height, HeightSource};
+
+use super::ExportError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeightmapFormat {
+    /// 16-bit grayscale, normalised between the minimum and maximum height.
+    Png16,
+    /// Headerless little-endian `f32` heights in world units.
+    RawF32,
+    /// Single precision OpenEXR with the height in every channel.
+    Exr,
+}
+
+impl HeightmapFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            HeightmapFormat::Png16 => "png",
+            HeightmapFormat::RawF32 => "raw",
+            HeightmapFormat::Exr => "exr",
+        }
+    }
+}
+
+/// What to bake: `resolution` samples spread evenly over `region`, first
+/// row along `region.min.y`.
+#[derive(Clone)]
+pub struct HeightmapExport {
+    pub path: PathBuf,
+    pub region: Rect,
+    pub resolution: UVec2,
+    pub format: HeightmapFormat,
+}
+
+impl Default for HeightmapExport {
+    fn default() -> Self {
+        Self {
+            path: "exports/heightmap.png".into(),
+            region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            resolution: UVec2::splat(1024),
+            format: HeightmapFormat::Png16,
+        }
+    }
+}
+
+/// Written next to every exported heightmap as `<name>.ron`. Pixel values of a
+/// PNG map to heights as `min_height + value / 65535 * (max_height - min_height)`,
+/// which is also what the heightmap importer needs as offset and vertical scale.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeightmapMetadata {
+    pub format: HeightmapFormat,
+    pub width: u32,
+    pub height: u32,
+    pub region_min: [f32; 2],
+    pub region_max: [f32; 2],
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+/// Heights over `region` on a `resolution` grid, row-major.
+pub fn sample_heightfield(source: &dyn HeightSource, region: Rect, resolution: UVec2) -> Vec<f32> {
+    let resolution = resolution.max(UVec2::splat(2));
+    let spacing = region.size() / (resolution - 1).as_vec2();
+
+    let mut heights = Vec::with_capacity((resolution.x * resolution.y) as usize);
+    for j in 0..resolution.y {
+        for i in 0..resolution.x {
+            let point = region.min + UVec2::new(i, j).as_vec2() * spacing;
+            heights.push(sample_height(source, point, spacing.min_element()));
+        }
+    }
+
+    heights
+}
+
+/// Bakes `source` as described by `export` and writes the heightmap and its
+/// metadata sidecar, creating parent folders as needed.
+pub fn export_heightmap(
+    source: &dyn HeightSource,
+    export: &HeightmapExport,
+) -> Result<HeightmapMetadata, ExportError> {
+    let resolution = export.resolution.max(UVec2::splat(2));
+    let heights = sample_heightfield(source, export.region, resolution);
+
+    let (min_height, max_height) = heights.iter().fold((f32::MAX, f32::MIN), |(min, max), &h| {
+        (min.min(h), max.max(h))
+    });
+
+    if let Some(parent) = export.path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match export.format {
+        HeightmapFormat::Png16 => {
+            let range = (max_height - min_height).max(f32::EPSILON);
+            let pixels = heights
+                .iter()
+                .map(|h| ((h - min_height) / range * u16::MAX as f32).round() as u16)
+                .collect();
+
+            ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(resolution.x, resolution.y, pixels)
+                .unwrap()
+                .save_with_format(&export.path, ImageFormat::Png)?;
+        }
+        HeightmapFormat::RawF32 => {
+            let bytes: Vec<u8> = heights.iter().flat_map(|h| h.to_le_bytes()).collect();
+            fs::write(&export.path, bytes)?;
+        }
+        HeightmapFormat::Exr => {
+            let pixels = heights.iter().flat_map(|&h| [h, h, h]).collect();
+
+            ImageBuffer::<Rgb<f32>, Vec<f32>>::from_raw(resolution.x, resolution.y, pixels)
+                .unwrap()
+                .save_with_format(&export.path, ImageFormat::OpenExr)?;
+        }
+    }
+
+    let metadata = HeightmapMetadata {
+        format: export.format,
+        width: resolution.x,
+        height: resolution.y,
+        region_min: export.region.min.to_array(),
+        region_max: export.region.max.to_array(),
+        min_height,
+        max_height,
+    };
+
+    let sidecar = ron::ser::to_string_pretty(&metadata, ron::ser::PrettyConfig::default())?;
+    fs::write(export.path.with_extension("ron"), sidecar)?;
+
+    Ok(metadata)
+}
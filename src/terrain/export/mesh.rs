@@ -0,0 +1,413 @@
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::{
+    prelude::*,
+    render::mesh::{Indices, VertexAttributeValues},
+};
+
+use crate::terrain::{
+    generation::{BiomeMap, ChunkGenerator, HeightSource},
+    resources::{SeamMode, SeamSettings},
+};
+
+use super::ExportError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    /// Binary glTF 2.0, one node per chunk carrying its transform.
+    Glb,
+    /// Wavefront OBJ, one object per chunk with vertices baked to world space.
+    Obj,
+}
+
+impl MeshFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            MeshFormat::Glb => "glb",
+            MeshFormat::Obj => "obj",
+        }
+    }
+
+    /// Format matching `path`'s extension, if it is one we can write.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "glb" => Some(MeshFormat::Glb),
+            "obj" => Some(MeshFormat::Obj),
+            _ => None,
+        }
+    }
+}
+
+/// What the terrain UI's mesh export writes. Region exports rebuild `region`
+/// from chunks the size of LOD tree nodes at `depth`.
+#[derive(Clone)]
+pub struct MeshExport {
+    pub path: PathBuf,
+    pub format: MeshFormat,
+    pub region: Rect,
+    pub depth: usize,
+}
+
+impl Default for MeshExport {
+    fn default() -> Self {
+        Self {
+            path: "exports/terrain.glb".into(),
+            format: MeshFormat::Glb,
+            region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            depth: 6,
+        }
+    }
+}
+
+/// Generates `region` of the flat world as a single mesh with `resolution`
+/// quads per side, the same way a chunk covering it would be. Positions are
+/// in grid units; the returned transform scales them to world space.
+pub fn generate_region_mesh(
+    source: Arc<dyn HeightSource>,
+    biomes: Arc<BiomeMap>,
+    region: Rect,
+    resolution: u32,
+) -> (Mesh, Transform) {
+    let resolution = resolution.max(1);
+    let scale = region.size() / resolution as f32;
+
+    let mut generator = ChunkGenerator::new(source, biomes);
+    generator.resolution = resolution;
+    generator.position = region.min;
+    generator.scale = scale;
+    generator.seams = SeamSettings {
+        mode: SeamMode::None,
+        ..Default::default()
+    };
+
+    let transform = Transform::from_xyz(region.min.x, 0.0, region.min.y)
+        .with_scale(Vec3::new(scale.x, 1.0, scale.y));
+
+    (generator.generate(), transform)
+}
+
+/// Covers `region` with chunks of `chunk_size`, aligned to the world origin
+/// like the leaves of the flat world's LOD tree.
+pub fn generate_region_chunks(
+    source: Arc<dyn HeightSource>,
+    biomes: Arc<BiomeMap>,
+    region: Rect,
+    chunk_size: Vec2,
+    resolution: u32,
+) -> Vec<(Mesh, Transform)> {
+    let first = (region.min / chunk_size).floor().as_ivec2();
+    let last = (region.max / chunk_size).ceil().as_ivec2();
+
+    let mut chunks = Vec::new();
+    for j in first.y..last.y {
+        for i in first.x..last.x {
+            let min = IVec2::new(i, j).as_vec2() * chunk_size;
+            chunks.push(generate_region_mesh(
+                source.clone(),
+                biomes.clone(),
+                Rect::from_corners(min, min + chunk_size),
+                resolution,
+            ));
+        }
+    }
+
+    chunks
+}
+
+/// Writes meshes in `format`, creating parent folders as needed.
+pub fn export_meshes<'a>(
+    meshes: impl IntoIterator<Item = (&'a Mesh, GlobalTransform)>,
+    format: MeshFormat,
+    path: &Path,
+) -> Result<(), ExportError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match format {
+        MeshFormat::Glb => write_glb(meshes, path),
+        MeshFormat::Obj => write_obj(meshes, path),
+    }
+}
+
+/// The attributes both formats carry, with missing indices filled in.
+struct MeshData<'a> {
+    positions: &'a [[f32; 3]],
+    normals: Option<&'a [[f32; 3]]>,
+    uvs: Option<&'a [[f32; 2]]>,
+    indices: Vec<u32>,
+}
+
+impl<'a> MeshData<'a> {
+    fn new(mesh: &'a Mesh) -> Option<Self> {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => Some(normals.as_slice()),
+            _ => None,
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs.as_slice()),
+            _ => None,
+        };
+
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            Some(Indices::U16(indices)) => indices.iter().map(|&i| i as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        Some(Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+        })
+    }
+}
+
+/// Writes meshes as one Wavefront OBJ, one object each, with positions and
+/// normals in world space.
+pub fn write_obj<'a>(
+    meshes: impl IntoIterator<Item = (&'a Mesh, GlobalTransform)>,
+    path: &Path,
+) -> Result<(), ExportError> {
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    let mut first_vertex = 1;
+
+    for (index, (mesh, transform)) in meshes.into_iter().enumerate() {
+        let Some(data) = MeshData::new(mesh) else {
+            continue;
+        };
+
+        let affine = transform.affine();
+        let normal_matrix = Mat3::from(affine.matrix3).inverse().transpose();
+
+        writeln!(out, "o chunk_{index}")?;
+        for &position in data.positions {
+            let p = affine.transform_point3(Vec3::from(position));
+            writeln!(out, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for &normal in data.normals.into_iter().flatten() {
+            let n = (normal_matrix * Vec3::from(normal)).normalize_or_zero();
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        for &[u, v] in data.uvs.into_iter().flatten() {
+            writeln!(out, "vt {u} {v}")?;
+        }
+
+        let corner = |i: u32| {
+            let i = i + first_vertex;
+            match (data.uvs.is_some(), data.normals.is_some()) {
+                (true, true) => format!("{i}/{i}/{i}"),
+                (false, true) => format!("{i}//{i}"),
+                (true, false) => format!("{i}/{i}"),
+                (false, false) => format!("{i}"),
+            }
+        };
+        for triangle in data.indices.chunks_exact(3) {
+            writeln!(
+                out,
+                "f {} {} {}",
+                corner(triangle[0]),
+                corner(triangle[1]),
+                corner(triangle[2])
+            )?;
+        }
+
+        first_vertex += data.positions.len() as u32;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Little-endian bytes of a vertex attribute or index, as glTF stores them.
+trait GltfComponent: Copy {
+    const COMPONENT_TYPE: u32;
+
+    fn write(self, out: &mut Vec<u8>);
+    fn as_f64(self) -> f64;
+}
+
+impl GltfComponent for f32 {
+    const COMPONENT_TYPE: u32 = GLTF_FLOAT;
+
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl GltfComponent for u32 {
+    const COMPONENT_TYPE: u32 = GLTF_UNSIGNED_INT;
+
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+/// Builds the JSON and binary chunk of a glTF file side by side.
+#[derive(Default)]
+struct GltfBuilder {
+    buffer: Vec<u8>,
+    views: Vec<String>,
+    accessors: Vec<String>,
+    meshes: Vec<String>,
+    nodes: Vec<String>,
+}
+
+impl GltfBuilder {
+    /// Appends `values` to the buffer and returns the index of the accessor
+    /// reading them back.
+    fn push<T: GltfComponent, const N: usize>(&mut self, values: &[[T; N]], target: u32) -> usize {
+        let offset = self.buffer.len();
+        for value in values {
+            value.iter().for_each(|v| v.write(&mut self.buffer));
+        }
+
+        self.views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{},"target":{target}}}"#,
+            self.buffer.len() - offset
+        ));
+
+        let kind = match N {
+            1 => "SCALAR",
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+
+        // Only required for positions, but cheap enough to always include.
+        let mut min = [f64::MAX; N];
+        let mut max = [f64::MIN; N];
+        for value in values {
+            for (c, v) in value.iter().enumerate() {
+                min[c] = min[c].min(v.as_f64());
+                max[c] = max[c].max(v.as_f64());
+            }
+        }
+        let bounds = if values.is_empty() {
+            String::new()
+        } else {
+            format!(r#","min":{min:?},"max":{max:?}"#)
+        };
+
+        self.accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{kind}"{bounds}}}"#,
+            self.views.len() - 1,
+            T::COMPONENT_TYPE,
+            values.len()
+        ));
+        self.accessors.len() - 1
+    }
+
+    fn add(&mut self, data: &MeshData, transform: GlobalTransform) {
+        let position = self.push(data.positions, GLTF_ARRAY_BUFFER);
+        let mut attributes = format!(r#""POSITION":{position}"#);
+
+        if let Some(normals) = data.normals {
+            let normal = self.push(normals, GLTF_ARRAY_BUFFER);
+            attributes += &format!(r#","NORMAL":{normal}"#);
+        }
+        if let Some(uvs) = data.uvs {
+            let uv = self.push(uvs, GLTF_ARRAY_BUFFER);
+            attributes += &format!(r#","TEXCOORD_0":{uv}"#);
+        }
+
+        let indices: Vec<[u32; 1]> = data.indices.iter().map(|&i| [i]).collect();
+        let indices = self.push(&indices, GLTF_ELEMENT_ARRAY_BUFFER);
+
+        self.meshes.push(format!(
+            r#"{{"primitives":[{{"attributes":{{{attributes}}},"indices":{indices}}}]}}"#
+        ));
+
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+        self.nodes.push(format!(
+            r#"{{"name":"chunk_{}","mesh":{},"translation":{:?},"rotation":{:?},"scale":{:?}}}"#,
+            self.nodes.len(),
+            self.meshes.len() - 1,
+            translation.to_array(),
+            rotation.to_array(),
+            scale.to_array()
+        ));
+    }
+
+    fn json(&self) -> String {
+        let scene: Vec<usize> = (0..self.nodes.len()).collect();
+
+        format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"planetgame-rs"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":{:?}}}],"#,
+                r#""nodes":[{}],"meshes":[{}],"accessors":[{}],"bufferViews":[{}],"#,
+                r#""buffers":[{{"byteLength":{}}}]}}"#
+            ),
+            scene,
+            self.nodes.join(","),
+            self.meshes.join(","),
+            self.accessors.join(","),
+            self.views.join(","),
+            self.buffer.len()
+        )
+    }
+}
+
+/// Writes meshes as a binary glTF 2.0 file with one node per mesh, keeping
+/// each transform on its node instead of baking it into the vertices.
+pub fn write_glb<'a>(
+    meshes: impl IntoIterator<Item = (&'a Mesh, GlobalTransform)>,
+    path: &Path,
+) -> Result<(), ExportError> {
+    let mut gltf = GltfBuilder::default();
+    for (mesh, transform) in meshes {
+        if let Some(data) = MeshData::new(mesh) {
+            gltf.add(&data, transform);
+        }
+    }
+
+    // Both chunks are 4-byte aligned, JSON padded with spaces, binary with zeros.
+    let mut json = gltf.json().into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut bin = gltf.buffer;
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    out.write_all(b"glTF")?;
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&(length as u32).to_le_bytes())?;
+
+    out.write_all(&(json.len() as u32).to_le_bytes())?;
+    out.write_all(b"JSON")?;
+    out.write_all(&json)?;
+
+    out.write_all(&(bin.len() as u32).to_le_bytes())?;
+    out.write_all(b"BIN\0")?;
+    out.write_all(&bin)?;
+
+    out.flush()?;
+    Ok(())
+}
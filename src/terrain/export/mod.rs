@@ -0,0 +1,47 @@
+use std::fmt;
+
+mod heightmap;
+mod mesh;
+mod rivers;
+mod stats;
+pub use heightmap::*;
+pub use mesh::*;
+pub use rivers::*;
+pub use stats::*;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Metadata(ron::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(err) => write!(f, "could not write export: {err}"),
+            ExportError::Image(err) => write!(f, "could not encode image: {err}"),
+            ExportError::Metadata(err) => write!(f, "could not write metadata: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<std::io::Error> for ExportError {
+    fn from(err: std::io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(err: image::ImageError) -> Self {
+        ExportError::Image(err)
+    }
+}
+
+impl From<ron::Error> for ExportError {
+    fn from(err: ron::Error) -> Self {
+        ExportError::Metadata(err)
+    }
+}
@@ -0,0 +1,18 @@
+use std::{fs, path::Path};
+
+use crate::terrain::generation::RiverNetwork;
+
+use super::ExportError;
+
+/// Writes the river splines as pretty RON, for tools and gameplay code that
+/// want them without running the generator.
+pub fn export_rivers(network: &RiverNetwork, path: &Path) -> Result<(), ExportError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let contents = ron::ser::to_string_pretty(network, ron::ser::PrettyConfig::default())?;
+    fs::write(path, contents)?;
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::terrain::generation::{Biome, BiomeMap, HeightSource};
+
+use super::sample_heightfield;
+
+/// Summary of a baked region, meant for comparing worlds between builds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TerrainStats {
+    pub region_min: [f32; 2],
+    pub region_max: [f32; 2],
+    pub samples: u32,
+    pub min_height: f32,
+    pub max_height: f32,
+    pub mean_height: f32,
+    pub height_std_dev: f32,
+    /// Mean and steepest slope between neighbouring samples, in degrees.
+    pub mean_slope: f32,
+    pub max_slope: f32,
+    /// Share of samples in each biome, by name.
+    pub biomes: Vec<(String, f32)>,
+}
+
+pub fn terrain_stats(
+    source: &dyn HeightSource,
+    biomes: &BiomeMap,
+    region: Rect,
+    resolution: UVec2,
+) -> TerrainStats {
+    let resolution = resolution.max(UVec2::splat(2));
+    let heights = sample_heightfield(source, region, resolution);
+    let spacing = region.size() / (resolution - 1).as_vec2();
+    let count = heights.len() as f32;
+
+    let (min_height, max_height) = heights.iter().fold((f32::MAX, f32::MIN), |(min, max), &h| {
+        (min.min(h), max.max(h))
+    });
+    let mean_height = heights.iter().sum::<f32>() / count;
+    let variance = heights
+        .iter()
+        .map(|h| (h - mean_height).powi(2))
+        .sum::<f32>()
+        / count;
+
+    let width = resolution.x as usize;
+    let mut slope_total = 0.0;
+    let mut max_slope = 0.0f32;
+    let mut biome_counts = [0u32; Biome::COUNT];
+
+    for (index, &height) in heights.iter().enumerate() {
+        let (i, j) = (index % width, index / width);
+
+        let dx = if i + 1 < width {
+            heights[index + 1] - height
+        } else {
+            height - heights[index - 1]
+        } / spacing.x;
+        let dz = if j + 1 < resolution.y as usize {
+            heights[index + width] - height
+        } else {
+            height - heights[index - width]
+        } / spacing.y;
+
+        let slope = dx.hypot(dz).atan().to_degrees();
+        slope_total += slope;
+        max_slope = max_slope.max(slope);
+
+        let point = region.min + Vec2::new(i as f32, j as f32) * spacing;
+        let climate = biomes.climate(point.x as f64, point.y as f64, height as f64);
+        biome_counts[biomes.biome(climate) as usize] += 1;
+    }
+
+    TerrainStats {
+        region_min: region.min.to_array(),
+        region_max: region.max.to_array(),
+        samples: heights.len() as u32,
+        min_height,
+        max_height,
+        mean_height,
+        height_std_dev: variance.sqrt(),
+        mean_slope: slope_total / count,
+        max_slope,
+        biomes: Biome::iter()
+            .zip(biome_counts)
+            .map(|(biome, samples)| (biome.to_string(), samples as f32 / count))
+            .collect(),
+    }
+}
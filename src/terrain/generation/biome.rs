@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::MeshVertexAttribute, render_resource::VertexFormat},
+};
+use noise::{NoiseFn, SuperSimplex};
+use strum::{Display, EnumCount, EnumIter, IntoEnumIterator};
+
+use crate::terrain::resources::{BiomeSettings, GenerationSettings};
+
+use super::LakeMap;
+
+/// Weights of the first four [`Biome`]s at each vertex, in declaration order.
+pub const ATTRIBUTE_BIOME_WEIGHTS_0: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_BiomeWeights0", 988540918, VertexFormat::Float32x4);
+
+/// Weights of the last four [`Biome`]s at each vertex.
+pub const ATTRIBUTE_BIOME_WEIGHTS_1: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_BiomeWeights1", 988540919, VertexFormat::Float32x4);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Display, EnumCount, EnumIter)]
+pub enum Biome {
+    Snow,
+    Tundra,
+    Taiga,
+    Grassland,
+    Forest,
+    Desert,
+    Savanna,
+    Rainforest,
+}
+
+impl Biome {
+    /// Where the biome sits in the (temperature, moisture) plane, both in `[0, 1]`.
+    fn climate(self) -> Vec2 {
+        match self {
+            Biome::Snow => Vec2::new(0.05, 0.6),
+            Biome::Tundra => Vec2::new(0.2, 0.3),
+            Biome::Taiga => Vec2::new(0.35, 0.7),
+            Biome::Grassland => Vec2::new(0.55, 0.35),
+            Biome::Forest => Vec2::new(0.55, 0.7),
+            Biome::Desert => Vec2::new(0.85, 0.1),
+            Biome::Savanna => Vec2::new(0.8, 0.4),
+            Biome::Rainforest => Vec2::new(0.85, 0.85),
+        }
+    }
+}
+
+pub type BiomeWeights = [f32; Biome::COUNT];
+
+// The weights are split across two four-component vertex attributes.
+const _: () = assert!(Biome::COUNT == 8);
+
+/// Temperature and moisture fields that pick a [`Biome`] together with the
+/// altitude of the terrain.
+pub struct BiomeMap {
+    temperature: SuperSimplex,
+    moisture: SuperSimplex,
+    settings: BiomeSettings,
+    lakes: Arc<LakeMap>,
+}
+
+impl BiomeMap {
+    pub fn new(settings: &GenerationSettings) -> Self {
+        Self {
+            temperature: SuperSimplex::new(settings.seed.wrapping_add(1)),
+            moisture: SuperSimplex::new(settings.seed.wrapping_add(2)),
+            settings: settings.biomes.clone(),
+            lakes: Arc::default(),
+        }
+    }
+
+    /// Makes the shores of `lakes` moister.
+    pub fn with_lakes(mut self, lakes: Arc<LakeMap>) -> Self {
+        self.lakes = lakes;
+        self
+    }
+
+    /// Temperature and moisture at a point on the flat world, at `height`.
+    pub fn climate(&self, x: f64, z: f64, height: f64) -> Vec2 {
+        let wetness = self
+            .lakes
+            .wetness(x as f32, z as f32, self.settings.lake_reach);
+        let climate = Vec2::new(
+            self.field(&self.temperature, [x, z]),
+            (self.field(&self.moisture, [x, z]) + wetness * self.settings.lake_moisture).min(1.0),
+        );
+
+        self.cool(climate, height)
+    }
+
+    /// Temperature and moisture at a point on the sphere in planet mode,
+    /// relative to the planet's center. The poles are colder than the equator.
+    pub fn climate_3d(&self, point: Vec3, radius: f32, height: f64) -> Vec2 {
+        let sample = point.as_dvec3().to_array();
+        let latitude = (point.y / radius).abs().clamp(0.0, 1.0);
+
+        let climate = Vec2::new(
+            self.field(&self.temperature, sample) - latitude * self.settings.polar_cooling,
+            self.field(&self.moisture, sample),
+        );
+
+        self.cool(climate, height)
+    }
+
+    fn cool(&self, climate: Vec2, height: f64) -> Vec2 {
+        let altitude = height.max(0.0) as f32;
+        Vec2::new(
+            (climate.x - altitude * self.settings.lapse_rate).clamp(0.0, 1.0),
+            climate.y,
+        )
+    }
+
+    /// Two octaves of noise remapped to `[0, 1]`.
+    fn field<const D: usize>(&self, noise: &SuperSimplex, point: [f64; D]) -> f32
+    where
+        SuperSimplex: NoiseFn<f64, D>,
+    {
+        let frequency = 1.0 / self.settings.climate_scale as f64;
+        let value = noise.get(point.map(|v| v * frequency)) * 0.75
+            + noise.get(point.map(|v| v * frequency * 4.0 + 17.0)) * 0.25;
+
+        (value as f32 * 0.5 + 0.5).clamp(0.0, 1.0)
+    }
+
+    /// Blend weights of every biome for a climate, summing to one.
+    pub fn weights(&self, climate: Vec2) -> BiomeWeights {
+        let blend = self.settings.blend.max(1e-3);
+        let distances = Biome::iter().map(|biome| biome.climate().distance_squared(climate));
+        let nearest = distances.clone().fold(f32::MAX, f32::min);
+
+        let mut weights = [0.0; Biome::COUNT];
+        for (weight, distance) in weights.iter_mut().zip(distances) {
+            *weight = (-(distance - nearest) / (blend * blend)).exp();
+        }
+
+        let total: f32 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    pub fn biome(&self, climate: Vec2) -> Biome {
+        Biome::iter()
+            .min_by(|a, b| {
+                let a = a.climate().distance_squared(climate);
+                let b = b.climate().distance_squared(climate);
+                a.total_cmp(&b)
+            })
+            .unwrap()
+    }
+}
@@ -1,30 +1,78 @@
-use std::thread;
+use std::sync::Arc;
 
 use bevy::{
     prelude::*,
-    render::{mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology},
+    render::{
+        mesh::{Indices, MeshVertexAttribute},
+        render_asset::RenderAssetUsages,
+        render_resource::{PrimitiveTopology, VertexFormat},
+    },
 };
-use noise::{NoiseFn, SuperSimplex};
-use rand::Rng;
 
-use crate::terrain::resources::GenerationSettings;
+use crate::terrain::{
+    planet::{CubeFace, PlanetSettings},
+    resources::{SeamMode, SeamSettings},
+};
+
+use super::{
+    BiomeMap, BiomeWeights, HeightSource, SculptLayer, ATTRIBUTE_BIOME_WEIGHTS_0,
+    ATTRIBUTE_BIOME_WEIGHTS_1,
+};
 
-pub use super::super::CHUNK_SIZE;
+/// Where each vertex would be if the chunk's parent were drawn instead, used to
+/// geomorph between LOD levels. Same space as [`Mesh::ATTRIBUTE_POSITION`].
+pub const ATTRIBUTE_MORPH_POSITION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_MorphPosition", 988540917, VertexFormat::Float32x3);
+
+/// Normal of the parent's surface at [`ATTRIBUTE_MORPH_POSITION`].
+pub const ATTRIBUTE_MORPH_NORMAL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_MorphNormal", 988540921, VertexFormat::Float32x3);
+
+#[derive(Clone, Copy)]
+pub enum ChunkShape {
+    /// A patch of the XZ plane. Vertices are in grid units and the chunk
+    /// entity scales them up to `scale`.
+    Flat,
+    /// A patch of a cube face projected onto the planet. `position` and `scale`
+    /// are in face space and vertices are in world units, relative to the
+    /// chunk's [`crate::terrain::lod_tree::LODTree::chunk_origin`].
+    Sphere {
+        face: CubeFace,
+        planet: PlanetSettings,
+    },
+}
 
 pub struct ChunkGenerator {
-    pub resolution: i32,
+    /// Quads along each side of the chunk.
+    pub resolution: u32,
     pub position: Vec2,
     pub scale: Vec2,
-    settings: GenerationSettings,
+    pub shape: ChunkShape,
+    pub seams: SeamSettings,
+    /// Boundary of the parent LOD node, in the same space as `position`.
+    /// Chunks without one morph onto themselves.
+    pub parent: Option<Rect>,
+    /// Quads along each side of the parent's chunk.
+    pub parent_resolution: u32,
+    /// Edits added on top of the height source. Only flat chunks use them.
+    pub sculpt: Arc<SculptLayer>,
+    source: Arc<dyn HeightSource>,
+    biomes: Arc<BiomeMap>,
 }
 
 impl ChunkGenerator {
-    pub fn new(settings: GenerationSettings) -> Self {
+    pub fn new(source: Arc<dyn HeightSource>, biomes: Arc<BiomeMap>) -> Self {
         Self {
-            settings,
+            source,
+            biomes,
             scale: Vec2::new(1.0, 1.0),
             position: Vec2::ZERO,
-            resolution: 1,
+            shape: ChunkShape::Flat,
+            seams: SeamSettings::default(),
+            parent: None,
+            resolution: 4,
+            parent_resolution: 4,
+            sculpt: Arc::default(),
         }
     }
 
@@ -34,74 +82,257 @@ impl ChunkGenerator {
             RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
         );
 
-        let noise = SuperSimplex::new(self.settings.seed);
+        let positions = self.generate_positions();
+
+        let mut vertices = generate_vertices(&positions, self.resolution);
+        let mut normals = generate_normals(&positions, self.resolution);
+        let (mut morph_targets, mut morph_normals) =
+            self.generate_morph_targets(&vertices, &normals);
+        let mut uvs = generate_uvs(self.resolution);
+        let mut biome_weights = self.generate_biome_weights(&positions);
+        let mut indices = generate_indices(self.resolution);
+
+        if self.seams.mode == SeamMode::Skirts {
+            let depth = self.seams.skirt_depth * self.scale.x * self.resolution as f32;
+            let origin = self.origin();
+            let down = |vertex: [f32; 3]| match self.shape {
+                ChunkShape::Flat => Vec3::NEG_Y * depth,
+                ChunkShape::Sphere { .. } => -(Vec3::from(vertex) + origin).normalize() * depth,
+            };
+
+            let first_skirt = vertices.len();
+            let tops = generate_skirts(&mut vertices, &mut indices, self.resolution, down);
+
+            for (skirt, &top) in vertices[first_skirt..].iter().zip(&tops) {
+                let offset = Vec3::from(*skirt) - Vec3::from(vertices[top as usize]);
+                morph_targets.push((Vec3::from(morph_targets[top as usize]) + offset).to_array());
+            }
+            extend_skirts(&mut normals, &tops);
+            extend_skirts(&mut morph_normals, &tops);
+            extend_skirts(&mut uvs, &tops);
+            extend_skirts(&mut biome_weights, &tops);
+        }
 
-        mesh.insert_attribute(
-            Mesh::ATTRIBUTE_POSITION,
-            generate_vertices(self.position, &noise, self.scale, &self.settings),
-        );
+        let (biome_weights_0, biome_weights_1): (Vec<[f32; 4]>, Vec<[f32; 4]>) = biome_weights
+            .iter()
+            .map(|weights| {
+                (
+                    [weights[0], weights[1], weights[2], weights[3]],
+                    [weights[4], weights[5], weights[6], weights[7]],
+                )
+            })
+            .unzip();
 
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, generate_normals());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
 
-        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, generate_uvs());
+        mesh.insert_attribute(ATTRIBUTE_MORPH_POSITION, morph_targets);
 
-        mesh.insert_indices(Indices::U32(generate_indices()));
+        mesh.insert_attribute(ATTRIBUTE_MORPH_NORMAL, morph_normals);
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+
+        mesh.insert_attribute(ATTRIBUTE_BIOME_WEIGHTS_0, biome_weights_0);
+
+        mesh.insert_attribute(ATTRIBUTE_BIOME_WEIGHTS_1, biome_weights_1);
+
+        mesh.insert_indices(Indices::U32(indices));
 
         mesh
     }
-}
 
-fn generate_vertices<T: NoiseFn<f64, 2>>(
-    position: Vec2,
-    noise: &T,
-    scale: Vec2,
-    settings: &GenerationSettings,
-) -> Vec<[f32; 3]> {
-    let mut vertices = Vec::new();
+    /// Offset from the planet's center to the point vertices are relative to.
+    fn origin(&self) -> Vec3 {
+        match self.shape {
+            ChunkShape::Flat => Vec3::ZERO,
+            ChunkShape::Sphere { face, planet } => {
+                let center = self.position + self.scale * (self.resolution as f32 / 2.0);
+                planet.surface_point(face, center) - planet.center()
+            }
+        }
+    }
 
-    for i in 0..CHUNK_SIZE + 1 {
-        for j in 0..CHUNK_SIZE + 1 {
-            let x = i as f32;
-            let z = j as f32;
+    /// Vertex positions on a grid with one extra sample past every chunk edge,
+    /// so normals along the border match the neighbouring chunk.
+    fn generate_positions(&self) -> Vec<Vec3> {
+        let padded = self.resolution as usize + 3;
+        let mut positions = Vec::with_capacity(padded * padded);
 
-            let nx = ((position.x + x * scale.x) * settings.scale) as f64;
-            let nz = ((position.y + z * scale.y) * settings.scale) as f64;
+        let origin = self.origin();
 
-            let g = 2.0f64.powf(-settings.persistence);
-            let mut total = 0f64;
-            let mut normalization = 0f64;
-            let mut amplitude = settings.amplitude;
-            let mut frequency = settings.frequency;
+        for i in -1..=self.resolution as i32 + 1 {
+            for j in -1..=self.resolution as i32 + 1 {
+                let point = self.position + Vec2::new(i as f32, j as f32) * self.scale;
+                positions.push(self.vertex(point, self.scale.x, origin));
+            }
+        }
 
-            for _ in 0..settings.octaves {
-                let noise_v = noise.get([nx * frequency * 0.5 + 0.5, nz * frequency * 0.5 + 0.5]);
+        positions
+    }
 
-                total += noise_v * amplitude;
-                normalization += amplitude;
-                amplitude *= g;
-                frequency *= settings.lacunarity;
+    /// Mesh space position of the terrain at `point`, which is in the same
+    /// space as `position`, for a grid with `spacing` between vertices.
+    fn vertex(&self, point: Vec2, spacing: f32, origin: Vec3) -> Vec3 {
+        match self.shape {
+            ChunkShape::Flat => {
+                let height = sample_height(self.source.as_ref(), point, spacing)
+                    + self.sculpt.height(point.x, point.y);
+                let grid = (point - self.position) / self.scale;
+                Vec3::new(grid.x, height, grid.y)
             }
+            ChunkShape::Sphere { face, planet } => {
+                let direction = face.direction(point / planet.radius);
+                let surface = (direction * planet.radius).as_dvec3();
+                let height = self.source.height_3d(surface.x, surface.y, surface.z);
 
-            total /= normalization;
+                direction * (planet.radius + height as f32) - origin
+            }
+        }
+    }
+
+    /// Biome weights at every grid vertex, using the heights already sampled
+    /// for `positions`.
+    fn generate_biome_weights(&self, positions: &[Vec3]) -> Vec<BiomeWeights> {
+        let origin = self.origin();
+        let mut weights = Vec::new();
+
+        for i in 0..self.resolution as i32 + 1 {
+            for j in 0..self.resolution as i32 + 1 {
+                let vertex = positions[padded_index(i, j, self.resolution)];
+
+                let climate = match self.shape {
+                    ChunkShape::Flat => {
+                        let point = self.position + Vec2::new(i as f32, j as f32) * self.scale;
+                        self.biomes
+                            .climate(point.x as f64, point.y as f64, vertex.y as f64)
+                    }
+                    ChunkShape::Sphere { planet, .. } => {
+                        let point = vertex + origin;
+                        let height = point.length() - planet.radius;
+                        self.biomes.climate_3d(
+                            point.normalize() * planet.radius,
+                            planet.radius,
+                            height as f64,
+                        )
+                    }
+                };
+
+                weights.push(self.biomes.weights(climate));
+            }
+        }
 
-            let mut y = total.powf(settings.exponentiation) * settings.height;
+        weights
+    }
 
-            if y.is_nan() {
-                y = 0.0;
+    /// Samples the parent's grid and interpolates it across the parent's
+    /// triangles at every vertex, which is exactly what the parent renders there.
+    /// Returns the positions with the parent's normals, interpolated the same way.
+    fn generate_morph_targets(
+        &self,
+        vertices: &[[f32; 3]],
+        normals: &[[f32; 3]],
+    ) -> (Vec<[f32; 3]>, Vec<[f32; 3]>) {
+        let Some(parent) = self.parent else {
+            return (vertices.to_vec(), normals.to_vec());
+        };
+
+        let origin = self.origin();
+        let parent_scale = parent.size() / self.parent_resolution as f32;
+
+        // Padded like `generate_positions`, for the parent's normals.
+        let mut parent_positions = Vec::new();
+        for i in -1..=self.parent_resolution as i32 + 1 {
+            for j in -1..=self.parent_resolution as i32 + 1 {
+                let point = parent.min + Vec2::new(i as f32, j as f32) * parent_scale;
+                parent_positions.push(self.vertex(point, parent_scale.x, origin));
             }
+        }
+        let parent_normals = generate_normals(&parent_positions, self.parent_resolution);
+
+        let parent_width = self.parent_resolution + 1;
+        let parent_vertex = |i: u32, j: u32| {
+            (
+                parent_positions[padded_index(i as i32, j as i32, self.parent_resolution)],
+                Vec3::from(parent_normals[(i * parent_width + j) as usize]),
+            )
+        };
+
+        let width = self.resolution + 1;
+        let mut targets = Vec::with_capacity(vertices.len());
+        let mut target_normals = Vec::with_capacity(vertices.len());
+        for i in 0..width {
+            for j in 0..width {
+                let point = self.position + Vec2::new(i as f32, j as f32) * self.scale;
+                let grid = (point - parent.min) / parent_scale;
+
+                let cell = grid
+                    .floor()
+                    .clamp(Vec2::ZERO, Vec2::splat((self.parent_resolution - 1) as f32));
+                let (u, v) = (grid.x - cell.x, grid.y - cell.y);
+                let (ci, cj) = (cell.x as u32, cell.y as u32);
+
+                // Quads are split along the diagonal from (i, j + 1) to (i + 1, j),
+                // matching `generate_indices`.
+                let (corner, along_u, along_v, u, v) = if u + v <= 1.0 {
+                    (
+                        parent_vertex(ci, cj),
+                        parent_vertex(ci + 1, cj),
+                        parent_vertex(ci, cj + 1),
+                        u,
+                        v,
+                    )
+                } else {
+                    (
+                        parent_vertex(ci + 1, cj + 1),
+                        parent_vertex(ci, cj + 1),
+                        parent_vertex(ci + 1, cj),
+                        1.0 - u,
+                        1.0 - v,
+                    )
+                };
+
+                let target = corner.0 + (along_u.0 - corner.0) * u + (along_v.0 - corner.0) * v;
+                let normal = corner.1 + (along_u.1 - corner.1) * u + (along_v.1 - corner.1) * v;
+
+                targets.push(target.to_array());
+                target_normals.push(normal.normalize_or_zero().to_array());
+            }
+        }
 
-            vertices.push([x, y as f32, z]);
+        (targets, target_normals)
+    }
+}
+
+/// Height of the flat world at `point` as sampled by a grid with `spacing`
+/// world units between vertices. Anything baking the terrain outside of chunk
+/// generation should go through this so it matches what is rendered.
+pub fn sample_height(source: &dyn HeightSource, point: Vec2, spacing: f32) -> f32 {
+    source.height_filtered(point.x as f64, point.y as f64, spacing as f64) as f32
+}
+
+/// Index into the padded position grid of a chunk with `resolution` quads per side.
+fn padded_index(i: i32, j: i32, resolution: u32) -> usize {
+    ((i + 1) * (resolution as i32 + 3) + (j + 1)) as usize
+}
+
+fn generate_vertices(positions: &[Vec3], resolution: u32) -> Vec<[f32; 3]> {
+    let mut vertices = Vec::new();
+
+    for i in 0..resolution as i32 + 1 {
+        for j in 0..resolution as i32 + 1 {
+            vertices.push(positions[padded_index(i, j, resolution)].to_array());
         }
     }
 
     vertices
 }
 
-fn generate_indices() -> Vec<u32> {
+fn generate_indices(resolution: u32) -> Vec<u32> {
     let mut indices = Vec::new();
 
-    let width = CHUNK_SIZE + 1;
-    let height = CHUNK_SIZE + 1;
+    let width = resolution + 1;
+    let height = resolution + 1;
 
     for i in 0..width - 1 {
         for j in 0..height - 1 {
@@ -123,24 +354,81 @@ fn generate_indices() -> Vec<u32> {
     indices
 }
 
-fn generate_normals() -> Vec<[f32; 3]> {
+fn generate_normals(positions: &[Vec3], resolution: u32) -> Vec<[f32; 3]> {
     let mut normals = Vec::new();
 
-    let width = CHUNK_SIZE + 1;
-    let height = CHUNK_SIZE + 1;
+    let width = resolution as i32 + 1;
+    let height = resolution as i32 + 1;
+    let index = |i, j| padded_index(i, j, resolution);
+
+    for i in 0..width {
+        for j in 0..height {
+            // Central differences in mesh space. For flat chunks the non-uniform
+            // chunk scale is applied later through the normal matrix.
+            let along_i = positions[index(i + 1, j)] - positions[index(i - 1, j)];
+            let along_j = positions[index(i, j + 1)] - positions[index(i, j - 1)];
 
-    for _ in 0..width {
-        for _ in 0..height {
-            normals.push([0.0, 1.0, 0.0]); // Assuming normal vector pointing upwards (0, 0, 1)
+            normals.push(along_j.cross(along_i).normalize().to_array());
         }
     }
     normals
 }
 
-fn generate_uvs() -> Vec<[f32; 2]> {
+/// Hangs a strip below every chunk edge so the gap to a neighbour at a
+/// different LOD depth is filled instead of showing the sky. Skirt vertices are
+/// appended after the grid; returns the edge vertex above each of them.
+fn generate_skirts(
+    vertices: &mut Vec<[f32; 3]>,
+    indices: &mut Vec<u32>,
+    resolution: u32,
+    down: impl Fn([f32; 3]) -> Vec3,
+) -> Vec<u32> {
+    let width = resolution + 1;
+    let last = resolution;
+    let index = |i: u32, j: u32| i * width + j;
+
+    // Each edge is walked so that `down × direction` points out of the chunk,
+    // which keeps the skirt triangles facing outwards.
+    let edges: [Vec<u32>; 4] = [
+        (0..width).map(|j| index(0, j)).collect(),
+        (0..width).rev().map(|j| index(last, j)).collect(),
+        (0..width).rev().map(|i| index(i, 0)).collect(),
+        (0..width).map(|i| index(i, last)).collect(),
+    ];
+
+    let mut tops = Vec::new();
+
+    for edge in edges {
+        let first_skirt = vertices.len() as u32;
+
+        for &top in &edge {
+            let vertex = vertices[top as usize];
+            vertices.push((Vec3::from(vertex) + down(vertex)).to_array());
+            tops.push(top);
+        }
+
+        for k in 0..edge.len() as u32 - 1 {
+            let (a, b) = (edge[k as usize], edge[k as usize + 1]);
+            let (skirt_a, skirt_b) = (first_skirt + k, first_skirt + k + 1);
+
+            indices.extend_from_slice(&[a, skirt_a, b, b, skirt_a, skirt_b]);
+        }
+    }
+
+    tops
+}
+
+/// Gives skirt vertices the attribute value of the edge vertex above them.
+fn extend_skirts<T: Copy>(attribute: &mut Vec<T>, tops: &[u32]) {
+    for &top in tops {
+        attribute.push(attribute[top as usize]);
+    }
+}
+
+fn generate_uvs(resolution: u32) -> Vec<[f32; 2]> {
     let mut uvs = Vec::new();
-    let width = CHUNK_SIZE + 1;
-    let height = CHUNK_SIZE + 1;
+    let width = resolution + 1;
+    let height = resolution + 1;
     for i in 0..width {
         for j in 0..height {
             let u = j as f32 / (width - 1) as f32; // Normalize to [0, 1]
@@ -0,0 +1,121 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::prelude::*;
+
+use super::HeightSource;
+
+/// Offsets of the eight neighbours of a cell, with their distance in cells.
+pub(super) const NEIGHBOURS: [(i32, i32, f32); 8] = [
+    (-1, -1, std::f32::consts::SQRT_2),
+    (0, -1, 1.0),
+    (1, -1, std::f32::consts::SQRT_2),
+    (-1, 0, 1.0),
+    (1, 0, 1.0),
+    (-1, 1, std::f32::consts::SQRT_2),
+    (0, 1, 1.0),
+    (1, 1, std::f32::consts::SQRT_2),
+];
+
+/// Samples of `source` over a square grid, `size` along each side of `region`.
+pub(super) struct DrainageGrid {
+    pub size: usize,
+    pub region: Rect,
+    pub heights: Vec<f32>,
+}
+
+impl DrainageGrid {
+    pub fn sample(source: &dyn HeightSource, region: Rect, size: usize) -> Self {
+        let size = size.max(2);
+        let cell = region.size() / (size - 1) as f32;
+
+        let mut heights = Vec::with_capacity(size * size);
+        for j in 0..size {
+            for i in 0..size {
+                let point = region.min + Vec2::new(i as f32, j as f32) * cell;
+                heights.push(source.height(point.x as f64, point.y as f64) as f32);
+            }
+        }
+
+        Self {
+            size,
+            region,
+            heights,
+        }
+    }
+
+    pub fn cell_size(&self) -> Vec2 {
+        self.region.size() / (self.size - 1) as f32
+    }
+
+    pub fn position(&self, index: usize) -> Vec2 {
+        let (i, j) = (index % self.size, index / self.size);
+        self.region.min + Vec2::new(i as f32, j as f32) * self.cell_size()
+    }
+
+    /// Indices of the neighbours of `index` inside the grid, with their
+    /// distance in cells.
+    pub fn neighbours(&self, index: usize) -> impl Iterator<Item = (usize, f32)> {
+        let size = self.size as i32;
+        let (i, j) = ((index % self.size) as i32, (index / self.size) as i32);
+
+        NEIGHBOURS.iter().filter_map(move |&(di, dj, distance)| {
+            let (ni, nj) = (i + di, j + dj);
+            (ni >= 0 && nj >= 0 && ni < size && nj < size)
+                .then_some(((nj * size + ni) as usize, distance))
+        })
+    }
+
+    pub fn is_border(&self, index: usize) -> bool {
+        let (i, j) = (index % self.size, index / self.size);
+        i == 0 || j == 0 || i == self.size - 1 || j == self.size - 1
+    }
+
+    /// Priority-flood (Barnes et al.): raises every cell that can't drain to
+    /// the border to the level it would spill over at, plus `epsilon` per cell
+    /// travelled so filled areas still slope towards their outlet. Ties are
+    /// broken by index, so the result only depends on the heights.
+    pub fn priority_flood(&self, epsilon: f32) -> Vec<f32> {
+        let mut filled = self.heights.clone();
+        let mut visited = vec![false; filled.len()];
+        let mut open = BinaryHeap::new();
+
+        for index in 0..filled.len() {
+            if self.is_border(index) {
+                visited[index] = true;
+                open.push(Reverse((Level(filled[index]), index)));
+            }
+        }
+
+        while let Some(Reverse((Level(level), index))) = open.pop() {
+            for (neighbour, _) in self.neighbours(index) {
+                if visited[neighbour] {
+                    continue;
+                }
+
+                visited[neighbour] = true;
+                filled[neighbour] = filled[neighbour].max(level + epsilon);
+                open.push(Reverse((Level(filled[neighbour]), neighbour)));
+            }
+        }
+
+        filled
+    }
+}
+
+/// Total order over heights for the flood's priority queue.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) struct Level(pub f32);
+
+impl Eq for Level {}
+
+impl PartialOrd for Level {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Level {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
@@ -0,0 +1,327 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::terrain::resources::{ErosionSettings, ThermalSettings};
+
+use super::{GridSampling, HeightGrid, HeightSource};
+
+/// Runs the enabled erosion stages over `source` within `settings.region` and
+/// returns how much each sample was lowered or raised. Deterministic for a
+/// given seed.
+pub fn erode(source: &dyn HeightSource, settings: &ErosionSettings, seed: u32) -> HeightGrid {
+    // Cells are square, with the resolution spread over the longer side, and
+    // the grid covers the region to the nearest cell along the other.
+    let resolution = settings.resolution.max(2);
+    let cell = settings.region.size().max_element() / (resolution - 1) as f32;
+    let cells = (settings.region.size() / cell).round().max(Vec2::ONE);
+    let (width, height) = (cells.x as usize + 1, cells.y as usize + 1);
+    let region = Rect::from_corners(settings.region.min, settings.region.min + cells * cell);
+
+    // Heights are simulated in cells so slopes, and with them the rates, don't
+    // depend on the region's scale.
+    let mut original = Vec::with_capacity(width * height);
+    for j in 0..height {
+        for i in 0..width {
+            let point = region.min + Vec2::new(i as f32, j as f32) * cell;
+            original.push(source.height(point.x as f64, point.y as f64) as f32 / cell);
+        }
+    }
+
+    let mut map = Heightmap {
+        width,
+        height,
+        values: original.clone(),
+    };
+
+    if settings.hydraulic {
+        let brush = Brush::new(settings.radius);
+        let mut rng = StdRng::seed_from_u64(seed as u64);
+
+        for _ in 0..settings.droplets {
+            let position = Vec2::new(
+                rng.gen_range(0.0..(width - 1) as f32),
+                rng.gen_range(0.0..(height - 1) as f32),
+            );
+            map.simulate_droplet(position, settings, &brush);
+        }
+    }
+
+    if settings.thermal.enabled {
+        map.relax(&settings.thermal);
+    }
+
+    // Fade the delta out towards the border so the region blends into the
+    // untouched terrain around it.
+    let border = (width.min(height) as f32 * 0.1).max(1.0);
+    let values = map
+        .values
+        .iter()
+        .zip(&original)
+        .enumerate()
+        .map(|(index, (eroded, original))| {
+            let (i, j) = ((index % width) as f32, (index / width) as f32);
+            let edge = i
+                .min(j)
+                .min((width - 1) as f32 - i)
+                .min((height - 1) as f32 - j);
+            let fade = (edge / border).clamp(0.0, 1.0);
+
+            (eroded - original) * cell * fade
+        })
+        .collect();
+
+    HeightGrid::new(region, width, height, values)
+}
+
+struct Heightmap {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl Heightmap {
+    fn index(&self, i: usize, j: usize) -> usize {
+        j * self.width + i
+    }
+
+    /// Height and gradient at a position inside the map.
+    fn sample(&self, position: Vec2) -> (f32, Vec2) {
+        let (i, j) = (position.x as usize, position.y as usize);
+        let (u, v) = (position.x - i as f32, position.y - j as f32);
+
+        let nw = self.values[self.index(i, j)];
+        let ne = self.values[self.index(i + 1, j)];
+        let sw = self.values[self.index(i, j + 1)];
+        let se = self.values[self.index(i + 1, j + 1)];
+
+        let gradient = Vec2::new(
+            (ne - nw) * (1.0 - v) + (se - sw) * v,
+            (sw - nw) * (1.0 - u) + (se - ne) * u,
+        );
+        let height =
+            nw * (1.0 - u) * (1.0 - v) + ne * u * (1.0 - v) + sw * (1.0 - u) * v + se * u * v;
+
+        (height, gradient)
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        let max = Vec2::new((self.width - 1) as f32, (self.height - 1) as f32);
+        position.cmpge(Vec2::ZERO).all() && position.cmplt(max).all()
+    }
+
+    fn simulate_droplet(&mut self, mut position: Vec2, settings: &ErosionSettings, brush: &Brush) {
+        let mut direction = Vec2::ZERO;
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..settings.lifetime {
+            let (i, j) = (position.x as usize, position.y as usize);
+            let (u, v) = (position.x - i as f32, position.y - j as f32);
+            let (height, gradient) = self.sample(position);
+
+            direction = (direction * settings.inertia - gradient * (1.0 - settings.inertia))
+                .normalize_or_zero();
+            if direction == Vec2::ZERO {
+                break;
+            }
+
+            let previous = position;
+            position += direction;
+            if !self.contains(position) {
+                break;
+            }
+
+            let delta_height = self.sample(position).0 - height;
+            let capacity = (-delta_height * speed * water * settings.capacity)
+                .max(settings.min_slope * speed * water * settings.capacity);
+
+            if sediment > capacity || delta_height > 0.0 {
+                // Fill the pit the droplet is climbing out of, or drop what it
+                // can no longer carry.
+                let deposit = if delta_height > 0.0 {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * settings.deposition
+                };
+                sediment -= deposit;
+
+                for (di, dj, weight) in [
+                    (0, 0, (1.0 - u) * (1.0 - v)),
+                    (1, 0, u * (1.0 - v)),
+                    (0, 1, (1.0 - u) * v),
+                    (1, 1, u * v),
+                ] {
+                    let index = self.index(i + di, j + dj);
+                    self.values[index] += deposit * weight;
+                }
+            } else {
+                let amount = ((capacity - sediment) * settings.erosion).min(-delta_height);
+                sediment += brush.apply(self, previous, amount);
+            }
+
+            speed = (speed * speed + delta_height * settings.gravity)
+                .max(0.0)
+                .sqrt();
+            water *= 1.0 - settings.evaporation;
+        }
+    }
+}
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+impl Heightmap {
+    /// Thermal erosion. Every cell sheds part of its height above the talus
+    /// slope to its lower neighbours, in proportion to how far each is below
+    /// it. Changes are gathered before being applied so the result doesn't
+    /// depend on iteration order.
+    fn relax(&mut self, settings: &ThermalSettings) {
+        let talus = settings.talus_angle.to_radians().tan();
+        let (columns, rows) = (self.width as i32, self.height as i32);
+        let mut changes = vec![0.0; self.values.len()];
+
+        for _ in 0..settings.iterations {
+            changes.fill(0.0);
+
+            for j in 0..rows {
+                for i in 0..columns {
+                    let index = self.index(i as usize, j as usize);
+                    let height = self.values[index];
+
+                    let mut excess = [0.0; 8];
+                    let mut total = 0.0;
+                    let mut steepest = 0.0f32;
+
+                    for (k, (di, dj)) in NEIGHBOURS.iter().enumerate() {
+                        let (ni, nj) = (i + di, j + dj);
+                        if ni < 0 || nj < 0 || ni >= columns || nj >= rows {
+                            continue;
+                        }
+
+                        let distance = ((di * di + dj * dj) as f32).sqrt();
+                        let neighbour = self.values[self.index(ni as usize, nj as usize)];
+                        let drop = height - neighbour - talus * distance;
+
+                        if drop > 0.0 {
+                            excess[k] = drop;
+                            total += drop;
+                            steepest = steepest.max(drop);
+                        }
+                    }
+
+                    if total <= 0.0 {
+                        continue;
+                    }
+
+                    let moved = steepest * 0.5 * settings.rate;
+                    changes[index] -= moved;
+
+                    for (k, (di, dj)) in NEIGHBOURS.iter().enumerate() {
+                        if excess[k] > 0.0 {
+                            let neighbour = self.index((i + di) as usize, (j + dj) as usize);
+                            changes[neighbour] += moved * excess[k] / total;
+                        }
+                    }
+                }
+            }
+
+            for (value, change) in self.values.iter_mut().zip(&changes) {
+                *value += change;
+            }
+        }
+    }
+}
+
+/// Cells within `radius` of a point, weighted towards the center, over which
+/// erosion is spread to avoid digging single-cell pits.
+struct Brush {
+    offsets: Vec<(i32, i32, f32)>,
+}
+
+impl Brush {
+    fn new(radius: u32) -> Self {
+        let radius = radius.max(1) as i32;
+        let mut offsets = Vec::new();
+        let mut total = 0.0;
+
+        for dj in -radius..=radius {
+            for di in -radius..=radius {
+                let distance = ((di * di + dj * dj) as f32).sqrt();
+                if distance <= radius as f32 {
+                    let weight = 1.0 - distance / radius as f32;
+                    total += weight;
+                    offsets.push((di, dj, weight));
+                }
+            }
+        }
+
+        for offset in &mut offsets {
+            offset.2 /= total;
+        }
+
+        Self { offsets }
+    }
+
+    /// Lowers the cells around `position` by `amount` in total and returns how
+    /// much was actually removed.
+    fn apply(&self, map: &mut Heightmap, position: Vec2, amount: f32) -> f32 {
+        let (i, j) = (position.x as i32, position.y as i32);
+        let mut removed = 0.0;
+
+        for &(di, dj, weight) in &self.offsets {
+            let (ci, cj) = (i + di, j + dj);
+            if ci < 0 || cj < 0 || ci >= map.width as i32 || cj >= map.height as i32 {
+                continue;
+            }
+
+            let index = map.index(ci as usize, cj as usize);
+            map.values[index] -= amount * weight;
+            removed += amount * weight;
+        }
+
+        removed
+    }
+}
+
+/// Wraps a height source with a precomputed erosion delta.
+pub struct ErodedHeightSource {
+    source: Arc<dyn HeightSource>,
+    delta: HeightGrid,
+}
+
+impl ErodedHeightSource {
+    pub fn new(source: Arc<dyn HeightSource>, settings: &ErosionSettings, seed: u32) -> Self {
+        let delta = erode(source.as_ref(), settings, seed);
+        Self { source, delta }
+    }
+}
+
+impl HeightSource for ErodedHeightSource {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.height_filtered(x, z, 0.0)
+    }
+
+    fn height_filtered(&self, x: f64, z: f64, footprint: f64) -> f64 {
+        let height = self.source.height_filtered(x, z, footprint);
+        if !self.delta.contains(x, z) {
+            return height;
+        }
+
+        height + self.delta.sample(x, z, footprint, GridSampling::Bilinear)
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.source.height_3d(x, y, z)
+    }
+}
@@ -0,0 +1,602 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use noise::{
+    core::worley::{distance_functions, worley_2d, worley_3d, ReturnType},
+    permutationtable::PermutationTable,
+    NoiseFn, Perlin, SuperSimplex,
+};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+
+use super::HeightSource;
+
+/// Folder scanned by the terrain UI for `.ron` recipes.
+pub const RECIPE_DIRECTORY: &str = "assets/terrain";
+
+/// A terrain recipe, described as a tree of noise nodes in a `.ron` file.
+///
+/// ```ron
+/// (
+///     height: 400.0,
+///     scale: 0.0005,
+///     root: Fbm(
+///         source: SuperSimplex(seed: 3),
+///         octaves: 8,
+///     ),
+/// )
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+pub struct NoiseGraph {
+    /// Multiplier applied to the root output, in world units.
+    #[serde(default = "one")]
+    pub height: f64,
+    /// Multiplier applied to world x/z before sampling the root.
+    #[serde(default = "one")]
+    pub scale: f64,
+    pub root: NoiseNode,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum NoiseNode {
+    // -- Sources --
+    SuperSimplex {
+        #[serde(default)]
+        seed: u32,
+        #[serde(default = "one")]
+        frequency: f64,
+    },
+    Perlin {
+        #[serde(default)]
+        seed: u32,
+        #[serde(default = "one")]
+        frequency: f64,
+    },
+    Worley {
+        #[serde(default)]
+        seed: u32,
+        #[serde(default = "one")]
+        frequency: f64,
+        /// Return the distance to the nearest cell point instead of a per-cell value.
+        #[serde(default = "yes")]
+        distance: bool,
+    },
+    Constant(f64),
+
+    // -- Combiners --
+    Add(Vec<NoiseNode>),
+    Multiply(Vec<NoiseNode>),
+    Min(Vec<NoiseNode>),
+    Max(Vec<NoiseNode>),
+    /// Picks `high` where `control` is above `threshold`, `low` otherwise,
+    /// blending smoothly over `falloff` on either side of the threshold.
+    Select {
+        control: Box<NoiseNode>,
+        low: Box<NoiseNode>,
+        high: Box<NoiseNode>,
+        #[serde(default)]
+        threshold: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+
+    // -- Modifiers --
+    Fbm(Fractal),
+    Ridged(Fractal),
+    Billow(Fractal),
+    ScaleBias {
+        source: Box<NoiseNode>,
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default)]
+        bias: f64,
+    },
+    /// Remaps the source through `(input, output)` control points with cubic
+    /// interpolation. Points must be sorted by input.
+    Curve {
+        source: Box<NoiseNode>,
+        points: Vec<(f64, f64)>,
+    },
+    Terrace {
+        source: Box<NoiseNode>,
+        points: Vec<f64>,
+        #[serde(default)]
+        invert: bool,
+    },
+    /// Offsets the sample position of `source` by `warp`, sampled once per axis.
+    DomainWarp {
+        source: Box<NoiseNode>,
+        warp: Box<NoiseNode>,
+        #[serde(default = "one")]
+        strength: f64,
+        #[serde(default)]
+        seed: u32,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Fractal {
+    pub source: Box<NoiseNode>,
+    /// Offsets every octave so layered copies of `source` don't line up.
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default = "default_octaves")]
+    pub octaves: usize,
+    #[serde(default = "default_lacunarity")]
+    pub lacunarity: f64,
+    #[serde(default = "default_persistence")]
+    pub persistence: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_octaves() -> usize {
+    6
+}
+
+fn default_lacunarity() -> f64 {
+    2.0
+}
+
+fn default_persistence() -> f64 {
+    0.5
+}
+
+#[derive(Debug)]
+pub enum NoiseGraphError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl fmt::Display for NoiseGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoiseGraphError::Io(err) => write!(f, "could not read noise graph: {err}"),
+            NoiseGraphError::Parse(err) => write!(f, "could not parse noise graph: {err}"),
+            NoiseGraphError::Invalid(reason) => write!(f, "invalid noise graph: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for NoiseGraphError {}
+
+/// Every `.ron` file in [`RECIPE_DIRECTORY`], sorted by name.
+pub fn list_recipes() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(RECIPE_DIRECTORY) else {
+        return Vec::new();
+    };
+
+    let mut recipes: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+        .collect();
+
+    recipes.sort();
+    recipes
+}
+
+impl NoiseGraph {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NoiseGraphError> {
+        let source = fs::read_to_string(path).map_err(NoiseGraphError::Io)?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, NoiseGraphError> {
+        ron::Options::default()
+            .with_default_extension(Extensions::UNWRAP_VARIANT_NEWTYPES)
+            .from_str(source)
+            .map_err(NoiseGraphError::Parse)
+    }
+
+    /// Builds the sampler for this graph. `seed` is added to every node seed,
+    /// so one recipe can produce many worlds.
+    pub fn compile(&self, seed: u32) -> Result<CompiledNoiseGraph, NoiseGraphError> {
+        Ok(CompiledNoiseGraph {
+            height: self.height,
+            scale: self.scale,
+            root: Node::compile(&self.root, seed)?,
+        })
+    }
+}
+
+/// A [`NoiseGraph`] with all of its generators constructed, ready to sample.
+pub struct CompiledNoiseGraph {
+    height: f64,
+    scale: f64,
+    root: Node,
+}
+
+impl HeightSource for CompiledNoiseGraph {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.sample([x, z])
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.sample([x, y, z])
+    }
+}
+
+impl CompiledNoiseGraph {
+    fn sample<const D: usize>(&self, point: [f64; D]) -> f64
+    where
+        SuperSimplex: NoiseFn<f64, D>,
+        Perlin: NoiseFn<f64, D>,
+    {
+        let y = self.root.get(point.map(|v| v * self.scale)) * self.height;
+
+        if y.is_nan() {
+            0.0
+        } else {
+            y
+        }
+    }
+}
+
+enum Node {
+    SuperSimplex(SuperSimplex, f64),
+    Perlin(Perlin, f64),
+    Worley(PermutationTable, f64, ReturnType),
+    Constant(f64),
+    Add(Vec<Node>),
+    Multiply(Vec<Node>),
+    Min(Vec<Node>),
+    Max(Vec<Node>),
+    Select {
+        control: Box<Node>,
+        low: Box<Node>,
+        high: Box<Node>,
+        threshold: f64,
+        falloff: f64,
+    },
+    Fractal {
+        source: Box<Node>,
+        kind: FractalKind,
+        seed: u32,
+        octaves: usize,
+        lacunarity: f64,
+        persistence: f64,
+    },
+    ScaleBias(Box<Node>, f64, f64),
+    Curve(Box<Node>, Vec<(f64, f64)>),
+    Terrace(Box<Node>, Vec<f64>, bool),
+    DomainWarp {
+        source: Box<Node>,
+        warp: Box<Node>,
+        strength: f64,
+        seed: u32,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum FractalKind {
+    Fbm,
+    Ridged,
+    Billow,
+}
+
+impl Node {
+    fn compile(node: &NoiseNode, world_seed: u32) -> Result<Self, NoiseGraphError> {
+        let seed = |seed: u32| seed.wrapping_add(world_seed);
+        let boxed = |node: &NoiseNode| Node::compile(node, world_seed).map(Box::new);
+        let list = |name: &str, nodes: &[NoiseNode]| {
+            if nodes.is_empty() {
+                return Err(NoiseGraphError::Invalid(format!(
+                    "{name} needs at least one input"
+                )));
+            }
+
+            nodes
+                .iter()
+                .map(|node| Node::compile(node, world_seed))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match node {
+            NoiseNode::SuperSimplex { seed: s, frequency } => {
+                Node::SuperSimplex(SuperSimplex::new(seed(*s)), *frequency)
+            }
+            NoiseNode::Perlin { seed: s, frequency } => {
+                Node::Perlin(Perlin::new(seed(*s)), *frequency)
+            }
+            NoiseNode::Worley {
+                seed: s,
+                frequency,
+                distance,
+            } => Node::Worley(
+                PermutationTable::new(seed(*s)),
+                *frequency,
+                if *distance {
+                    ReturnType::Distance
+                } else {
+                    ReturnType::Value
+                },
+            ),
+            NoiseNode::Constant(value) => Node::Constant(*value),
+            NoiseNode::Add(nodes) => Node::Add(list("Add", nodes)?),
+            NoiseNode::Multiply(nodes) => Node::Multiply(list("Multiply", nodes)?),
+            NoiseNode::Min(nodes) => Node::Min(list("Min", nodes)?),
+            NoiseNode::Max(nodes) => Node::Max(list("Max", nodes)?),
+            NoiseNode::Select {
+                control,
+                low,
+                high,
+                threshold,
+                falloff,
+            } => Node::Select {
+                control: boxed(control)?,
+                low: boxed(low)?,
+                high: boxed(high)?,
+                threshold: *threshold,
+                falloff: falloff.max(0.0),
+            },
+            NoiseNode::Fbm(fractal) => Node::Fractal {
+                source: boxed(&fractal.source)?,
+                kind: FractalKind::Fbm,
+                seed: seed(fractal.seed),
+                octaves: fractal.octaves,
+                lacunarity: fractal.lacunarity,
+                persistence: fractal.persistence,
+            },
+            NoiseNode::Ridged(fractal) => Node::Fractal {
+                source: boxed(&fractal.source)?,
+                kind: FractalKind::Ridged,
+                seed: seed(fractal.seed),
+                octaves: fractal.octaves,
+                lacunarity: fractal.lacunarity,
+                persistence: fractal.persistence,
+            },
+            NoiseNode::Billow(fractal) => Node::Fractal {
+                source: boxed(&fractal.source)?,
+                kind: FractalKind::Billow,
+                seed: seed(fractal.seed),
+                octaves: fractal.octaves,
+                lacunarity: fractal.lacunarity,
+                persistence: fractal.persistence,
+            },
+            NoiseNode::ScaleBias {
+                source,
+                scale,
+                bias,
+            } => Node::ScaleBias(boxed(source)?, *scale, *bias),
+            NoiseNode::Curve { source, points } => {
+                if points.len() < 2 {
+                    return Err(NoiseGraphError::Invalid(
+                        "Curve needs at least two points".into(),
+                    ));
+                }
+                if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                    return Err(NoiseGraphError::Invalid(
+                        "Curve points must be sorted by strictly increasing input".into(),
+                    ));
+                }
+                Node::Curve(boxed(source)?, points.clone())
+            }
+            NoiseNode::Terrace {
+                source,
+                points,
+                invert,
+            } => {
+                let mut points = points.clone();
+                points.sort_by(f64::total_cmp);
+                points.dedup();
+                if points.len() < 2 {
+                    return Err(NoiseGraphError::Invalid(
+                        "Terrace needs at least two distinct points".into(),
+                    ));
+                }
+                Node::Terrace(boxed(source)?, points, *invert)
+            }
+            NoiseNode::DomainWarp {
+                source,
+                warp,
+                strength,
+                seed: s,
+            } => Node::DomainWarp {
+                source: boxed(source)?,
+                warp: boxed(warp)?,
+                strength: *strength,
+                seed: seed(*s),
+            },
+        })
+    }
+
+    fn get<const D: usize>(&self, point: [f64; D]) -> f64
+    where
+        SuperSimplex: NoiseFn<f64, D>,
+        Perlin: NoiseFn<f64, D>,
+    {
+        match self {
+            Node::SuperSimplex(noise, frequency) => noise.get(point.map(|v| v * frequency)),
+            Node::Perlin(noise, frequency) => noise.get(point.map(|v| v * frequency)),
+            Node::Worley(table, frequency, return_type) => {
+                let point = point.map(|v| v * frequency);
+                match point.as_slice() {
+                    [x, z] => {
+                        worley_2d(table, distance_functions::euclidean, *return_type, [*x, *z])
+                    }
+                    [x, y, z] => worley_3d(
+                        table,
+                        distance_functions::euclidean,
+                        *return_type,
+                        [*x, *y, *z],
+                    ),
+                    _ => unreachable!("noise graphs are only sampled in 2D and 3D"),
+                }
+            }
+            Node::Constant(value) => *value,
+            Node::Add(nodes) => nodes.iter().map(|node| node.get(point)).sum(),
+            Node::Multiply(nodes) => nodes.iter().map(|node| node.get(point)).product(),
+            Node::Min(nodes) => nodes
+                .iter()
+                .map(|node| node.get(point))
+                .fold(f64::INFINITY, f64::min),
+            Node::Max(nodes) => nodes
+                .iter()
+                .map(|node| node.get(point))
+                .fold(f64::NEG_INFINITY, f64::max),
+            Node::Select {
+                control,
+                low,
+                high,
+                threshold,
+                falloff,
+            } => {
+                let control = control.get(point);
+
+                if *falloff <= 0.0 {
+                    return if control > *threshold {
+                        high.get(point)
+                    } else {
+                        low.get(point)
+                    };
+                }
+
+                let t = ((control - (threshold - falloff)) / (2.0 * falloff)).clamp(0.0, 1.0);
+                if t <= 0.0 {
+                    low.get(point)
+                } else if t >= 1.0 {
+                    high.get(point)
+                } else {
+                    let t = t * t * (3.0 - 2.0 * t);
+                    low.get(point) * (1.0 - t) + high.get(point) * t
+                }
+            }
+            Node::Fractal {
+                source,
+                kind,
+                seed,
+                octaves,
+                lacunarity,
+                persistence,
+            } => {
+                let mut total = 0.0;
+                let mut normalization = 0.0;
+                let mut amplitude = 1.0;
+                let mut frequency = 1.0;
+
+                for octave in 0..*octaves {
+                    let offset = seed_offset::<D>(*seed, octave as u32);
+                    let value =
+                        source.get(std::array::from_fn(|i| point[i] * frequency + offset[i]));
+
+                    let value = match kind {
+                        FractalKind::Fbm => value,
+                        FractalKind::Ridged => {
+                            let ridge = 1.0 - value.abs();
+                            ridge * ridge * 2.0 - 1.0
+                        }
+                        FractalKind::Billow => value.abs() * 2.0 - 1.0,
+                    };
+
+                    total += value * amplitude;
+                    normalization += amplitude;
+                    amplitude *= persistence;
+                    frequency *= lacunarity;
+                }
+
+                if normalization > 0.0 {
+                    total / normalization
+                } else {
+                    0.0
+                }
+            }
+            Node::ScaleBias(source, scale, bias) => source.get(point) * scale + bias,
+            Node::Curve(source, points) => curve(source.get(point), points),
+            Node::Terrace(source, points, invert) => terrace(source.get(point), points, *invert),
+            Node::DomainWarp {
+                source,
+                warp,
+                strength,
+                seed,
+            } => {
+                let warped = std::array::from_fn(|axis| {
+                    let offset = seed_offset::<D>(*seed, axis as u32);
+                    let shift = warp.get(std::array::from_fn(|i| point[i] + offset[i]));
+                    point[axis] + shift * strength
+                });
+
+                source.get(warped)
+            }
+        }
+    }
+}
+
+/// A pseudo-random, well separated offset so the same source can be sampled
+/// as if it were a differently seeded one.
+fn seed_offset<const D: usize>(seed: u32, index: u32) -> [f64; D] {
+    let mut hash = (seed as u64) << 32 | index as u64;
+    let mut next = || {
+        // SplitMix64
+        hash = hash.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = hash;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64 * 4096.0 - 2048.0
+    };
+
+    std::array::from_fn(|_| next())
+}
+
+fn curve(value: f64, points: &[(f64, f64)]) -> f64 {
+    let last = points.len() - 1;
+    let index = points
+        .iter()
+        .position(|point| point.0 > value)
+        .unwrap_or(points.len());
+
+    if index == 0 {
+        return points[0].1;
+    }
+    if index > last {
+        return points[last].1;
+    }
+
+    let p0 = points[index.saturating_sub(2)].1;
+    let (x1, p1) = points[index - 1];
+    let (x2, p2) = points[index];
+    let p3 = points[(index + 1).min(last)].1;
+
+    let t = (value - x1) / (x2 - x1);
+
+    // Catmull-Rom through the two surrounding points.
+    0.5 * (2.0 * p1
+        + (-p0 + p2) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t * t * t)
+}
+
+fn terrace(value: f64, points: &[f64], invert: bool) -> f64 {
+    let last = points.len() - 1;
+    let index = points
+        .iter()
+        .position(|point| *point > value)
+        .unwrap_or(points.len());
+
+    if index == 0 {
+        return points[0];
+    }
+    if index > last {
+        return points[last];
+    }
+
+    let (mut low, mut high) = (points[index - 1], points[index]);
+    let mut t = (value - low) / (high - low);
+
+    if invert {
+        t = 1.0 - t;
+        std::mem::swap(&mut low, &mut high);
+    }
+
+    t *= t;
+    low + (high - low) * t
+}
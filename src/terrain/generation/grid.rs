@@ -0,0 +1,221 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How values between grid samples are reconstructed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GridSampling {
+    #[default]
+    Bilinear,
+    /// Catmull-Rom, smoother than bilinear when a grid is magnified.
+    Bicubic,
+}
+
+/// Samples over a rectangle of the XZ plane, stored with a chain of
+/// tent-filtered mip levels so coarse chunks can sample a smoothed version.
+#[derive(Clone)]
+pub struct HeightGrid {
+    pub region: Rect,
+    /// Level 0 is full resolution, every following level halves it.
+    levels: Vec<GridLevel>,
+}
+
+#[derive(Clone)]
+struct GridLevel {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl HeightGrid {
+    /// `values` are row-major, with `width` samples along x spanning the
+    /// region from its min to its max edge.
+    pub fn new(region: Rect, width: usize, height: usize, values: Vec<f32>) -> Self {
+        assert!(width >= 2 && height >= 2);
+        assert_eq!(values.len(), width * height);
+
+        let mut levels = vec![GridLevel {
+            width,
+            height,
+            values,
+        }];
+        while let Some(level) = levels.last().unwrap().downsample() {
+            levels.push(level);
+        }
+
+        Self { region, levels }
+    }
+
+    /// Samples along x and z at full resolution.
+    pub fn size(&self) -> (usize, usize) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+
+    /// Full resolution values, row-major.
+    pub fn values(&self) -> &[f32] {
+        &self.levels[0].values
+    }
+
+    /// Distance between samples at full resolution, in world units.
+    pub fn cell_size(&self) -> Vec2 {
+        let (width, height) = self.size();
+        self.region.size() / Vec2::new((width - 1) as f32, (height - 1) as f32)
+    }
+
+    pub fn contains(&self, x: f64, z: f64) -> bool {
+        self.region.contains(Vec2::new(x as f32, z as f32))
+    }
+
+    /// Value at `x`, `z`, averaged over roughly `footprint` world units.
+    /// Points outside the region take the value at its nearest edge.
+    pub fn sample(&self, x: f64, z: f64, footprint: f64, sampling: GridSampling) -> f64 {
+        let cell = self.cell_size().min_element();
+        let level = (footprint as f32 / cell).max(1.0).log2().floor() as usize;
+        let level = &self.levels[level.min(self.levels.len() - 1)];
+
+        let point = Vec2::new(x as f32, z as f32);
+        let grid = (point - self.region.min) / self.region.size()
+            * Vec2::new((level.width - 1) as f32, (level.height - 1) as f32);
+
+        match sampling {
+            GridSampling::Bilinear => level.bilinear(grid) as f64,
+            GridSampling::Bicubic => level.bicubic(grid) as f64,
+        }
+    }
+}
+
+impl GridLevel {
+    fn get(&self, i: isize, j: isize) -> f32 {
+        let i = i.clamp(0, self.width as isize - 1) as usize;
+        let j = j.clamp(0, self.height as isize - 1) as usize;
+        self.values[j * self.width + i]
+    }
+
+    fn bilinear(&self, grid: Vec2) -> f32 {
+        let (i, j) = (grid.x.floor(), grid.y.floor());
+        let (u, v) = (grid.x - i, grid.y - j);
+        let (i, j) = (i as isize, j as isize);
+
+        let top = self.get(i, j) * (1.0 - u) + self.get(i + 1, j) * u;
+        let bottom = self.get(i, j + 1) * (1.0 - u) + self.get(i + 1, j + 1) * u;
+        top * (1.0 - v) + bottom * v
+    }
+
+    fn bicubic(&self, grid: Vec2) -> f32 {
+        let (i, j) = (grid.x.floor(), grid.y.floor());
+        let (u, v) = (grid.x - i, grid.y - j);
+        let (i, j) = (i as isize, j as isize);
+
+        let rows = [-1, 0, 1, 2]
+            .map(|dj| catmull_rom([-1, 0, 1, 2].map(|di| self.get(i + di, j + dj)), u));
+        catmull_rom(rows, v)
+    }
+
+    /// Half resolution copy, or `None` once a level is two samples wide.
+    /// Every level keeps its first and last samples on the region's edges,
+    /// like [`HeightGrid::sample`] expects.
+    fn downsample(&self) -> Option<GridLevel> {
+        if self.width <= 2 && self.height <= 2 {
+            return None;
+        }
+
+        // Halving only lines up with the corners when there is an even
+        // number of cells, all the way down, so odd sizes are resampled to a
+        // power of two cells first.
+        let source = self.resampled(aligned(self.width), aligned(self.height));
+        let width = halved(source.width);
+        let height = halved(source.height);
+
+        // One axis at a time, first along x for every row, then along z.
+        let mut rows = Vec::with_capacity(width * source.height);
+        for j in 0..source.height {
+            let row = &source.values[j * source.width..(j + 1) * source.width];
+            rows.extend((0..width).map(|i| decimate(|k| row[k], source.width, width, i)));
+        }
+
+        let mut values = vec![0.0; width * height];
+        for i in 0..width {
+            let column = |k: usize| rows[k * width + i];
+            for j in 0..height {
+                values[j * width + i] = decimate(column, source.height, height, j);
+            }
+        }
+
+        Some(GridLevel {
+            width,
+            height,
+            values,
+        })
+    }
+
+    /// Bilinear copy with `width` by `height` samples over the same region.
+    fn resampled(&self, width: usize, height: usize) -> GridLevel {
+        if width == self.width && height == self.height {
+            return self.clone();
+        }
+
+        let scale = Vec2::new(
+            (self.width - 1) as f32 / (width - 1) as f32,
+            (self.height - 1) as f32 / (height - 1) as f32,
+        );
+        let values = (0..height)
+            .flat_map(|j| (0..width).map(move |i| Vec2::new(i as f32, j as f32) * scale))
+            .map(|grid| self.bilinear(grid))
+            .collect();
+
+        GridLevel {
+            width,
+            height,
+            values,
+        }
+    }
+}
+
+/// Smallest number of samples from `samples` up that halves evenly down to
+/// two.
+fn aligned(samples: usize) -> usize {
+    if samples <= 2 {
+        return samples;
+    }
+    (samples - 1).next_power_of_two() + 1
+}
+
+/// Samples left after halving `samples`, keeping both ends.
+fn halved(samples: usize) -> usize {
+    if samples <= 2 {
+        return samples;
+    }
+    (samples - 1) / 2 + 1
+}
+
+/// Coarse sample `index` of a line of `fine` samples taken down to `coarse`.
+/// It sits on fine sample `2 * index`, filtered with a [1, 2, 1] tent. Past
+/// the ends the line is continued linearly, so the end samples keep their
+/// values.
+fn decimate(value: impl Fn(usize) -> f32, fine: usize, coarse: usize, index: usize) -> f32 {
+    if fine == coarse {
+        return value(index);
+    }
+
+    let center = index * 2;
+    let middle = value(center);
+    let left = match center {
+        0 => 2.0 * middle - value(center + 1),
+        _ => value(center - 1),
+    };
+    let right = match center + 1 < fine {
+        true => value(center + 1),
+        false => 2.0 * middle - value(center - 1),
+    };
+
+    (left + 2.0 * middle + right) * 0.25
+}
+
+fn catmull_rom(p: [f32; 4], t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * (2.0 * p[1]
+        + (p[2] - p[0]) * t
+        + (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3]) * t2
+        + (3.0 * p[1] - p[0] - 3.0 * p[2] + p[3]) * t3)
+}
@@ -0,0 +1,94 @@
+use noise::{NoiseFn, SuperSimplex};
+
+use crate::terrain::resources::GenerationSettings;
+
+/// Anything that can tell the height of the terrain at a world position.
+///
+/// Implement this to plug a custom generator into [`super::ChunkGenerator`] and
+/// assign it to [`crate::terrain::resources::Terrain::height_source`].
+pub trait HeightSource: Send + Sync {
+    fn height(&self, x: f64, z: f64) -> f64;
+
+    /// Height averaged over roughly `footprint` world units, used by coarse
+    /// chunks so detail smaller than their vertex spacing doesn't alias.
+    fn height_filtered(&self, x: f64, z: f64, footprint: f64) -> f64 {
+        let _ = footprint;
+        self.height(x, z)
+    }
+
+    /// Radial displacement at a point on the sphere in planet mode, relative to
+    /// the planet's center. Sources without a 3D form fall back to their planar
+    /// height, which stretches along y.
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        let _ = y;
+        self.height(x, z)
+    }
+}
+
+impl<F> HeightSource for F
+where
+    F: Fn(f64, f64) -> f64 + Send + Sync,
+{
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self(x, z)
+    }
+}
+
+/// The default terrain: fractal SuperSimplex noise driven by [`GenerationSettings`].
+pub struct FbmHeightSource {
+    noise: SuperSimplex,
+    settings: GenerationSettings,
+}
+
+impl FbmHeightSource {
+    pub fn new(settings: GenerationSettings) -> Self {
+        Self {
+            noise: SuperSimplex::new(settings.seed),
+            settings,
+        }
+    }
+
+    fn fbm<const D: usize>(&self, point: [f64; D]) -> f64
+    where
+        SuperSimplex: NoiseFn<f64, D>,
+    {
+        let settings = &self.settings;
+
+        let point = point.map(|v| v * settings.scale as f64);
+
+        let g = 2.0f64.powf(-settings.persistence);
+        let mut total = 0f64;
+        let mut normalization = 0f64;
+        let mut amplitude = settings.amplitude;
+        let mut frequency = settings.frequency;
+
+        for _ in 0..settings.octaves {
+            let noise_v = self.noise.get(point.map(|v| v * frequency * 0.5 + 0.5));
+
+            total += noise_v * amplitude;
+            normalization += amplitude;
+            amplitude *= g;
+            frequency *= settings.lacunarity;
+        }
+
+        total /= normalization;
+
+        let y = total.powf(settings.exponentiation) * settings.height;
+
+        if y.is_nan() {
+            0.0
+        } else {
+            y
+        }
+    }
+}
+
+impl HeightSource for FbmHeightSource {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.fbm([x, z])
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.fbm([x, y, z])
+    }
+}
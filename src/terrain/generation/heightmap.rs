@@ -0,0 +1,171 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::prelude::*;
+
+use crate::terrain::resources::HeightmapSettings;
+
+use super::{HeightGrid, HeightSource};
+
+/// Folder scanned by the terrain UI for heightmaps.
+pub const HEIGHTMAP_DIRECTORY: &str = "assets/heightmaps";
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Format(String),
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::Io(err) => write!(f, "could not read heightmap: {err}"),
+            HeightmapError::Image(err) => write!(f, "could not decode heightmap: {err}"),
+            HeightmapError::Format(reason) => write!(f, "invalid heightmap: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+/// Every `.png` and `.raw` file in [`HEIGHTMAP_DIRECTORY`], sorted by name.
+pub fn list_heightmaps() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(HEIGHTMAP_DIRECTORY) else {
+        return Vec::new();
+    };
+
+    let mut heightmaps: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "png" || ext == "raw" || ext == "r16")
+        })
+        .collect();
+
+    heightmaps.sort();
+    heightmaps
+}
+
+/// Reads a grayscale PNG, or a square headerless little-endian 16-bit RAW
+/// (`.raw` or `.r16`), into a grid over `extent` with values in `[0, 1]`.
+/// The image's first row lies along `extent.min.y`.
+pub fn load_heightmap(path: impl AsRef<Path>, extent: Rect) -> Result<HeightGrid, HeightmapError> {
+    let path = path.as_ref();
+    let is_raw = path
+        .extension()
+        .is_some_and(|ext| ext == "raw" || ext == "r16");
+
+    let (width, height, values) = if is_raw {
+        let bytes = fs::read(path).map_err(HeightmapError::Io)?;
+        let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+        if bytes.len() % 2 != 0 || size * size * 2 != bytes.len() {
+            return Err(HeightmapError::Format(format!(
+                "{} bytes is not a square 16-bit RAW",
+                bytes.len()
+            )));
+        }
+
+        let values = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as f32 / u16::MAX as f32)
+            .collect();
+
+        (size, size, values)
+    } else {
+        let image = image::io::Reader::open(path)
+            .map_err(HeightmapError::Io)?
+            .with_guessed_format()
+            .map_err(HeightmapError::Io)?
+            .decode()
+            .map_err(HeightmapError::Image)?
+            .into_luma16();
+
+        let (width, height) = image.dimensions();
+        let values = image
+            .into_raw()
+            .into_iter()
+            .map(|value| value as f32 / u16::MAX as f32)
+            .collect();
+
+        (width as usize, height as usize, values)
+    };
+
+    if width < 2 || height < 2 {
+        return Err(HeightmapError::Format(format!(
+            "{width}x{height} is too small"
+        )));
+    }
+
+    Ok(HeightGrid::new(extent, width, height, values))
+}
+
+/// Terrain from an imported heightmap inside its extent, blended with a
+/// procedural source, which also fills in the world around it.
+pub struct HeightmapSource {
+    grid: HeightGrid,
+    settings: HeightmapSettings,
+    base: Arc<dyn HeightSource>,
+}
+
+impl HeightmapSource {
+    pub fn load(
+        base: Arc<dyn HeightSource>,
+        settings: &HeightmapSettings,
+    ) -> Result<Self, HeightmapError> {
+        let Some(path) = &settings.path else {
+            return Err(HeightmapError::Format("no heightmap selected".into()));
+        };
+
+        Ok(Self {
+            grid: load_heightmap(path, settings.extent)?,
+            settings: settings.clone(),
+            base,
+        })
+    }
+
+    /// How much of the heightmap shows at a point, fading to zero over
+    /// `falloff` outside the extent.
+    fn mask(&self, x: f64, z: f64) -> f64 {
+        let point = Vec2::new(x as f32, z as f32);
+        let extent = self.settings.extent;
+        let outside = (extent.min - point).max(point - extent.max).max(Vec2::ZERO);
+        let distance = outside.length();
+
+        if self.settings.falloff <= 0.0 {
+            return if distance > 0.0 { 0.0 } else { 1.0 };
+        }
+
+        let t = (1.0 - distance / self.settings.falloff).clamp(0.0, 1.0) as f64;
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl HeightSource for HeightmapSource {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.height_filtered(x, z, 0.0)
+    }
+
+    fn height_filtered(&self, x: f64, z: f64, footprint: f64) -> f64 {
+        let noise = self.base.height_filtered(x, z, footprint);
+
+        let mask = self.mask(x, z);
+        if mask <= 0.0 {
+            return noise;
+        }
+
+        let settings = &self.settings;
+        let map = settings.offset as f64
+            + self.grid.sample(x, z, footprint, settings.sampling) * settings.vertical_scale as f64;
+        let blended = map + noise * settings.noise_blend as f64;
+
+        noise + (blended - noise) * mask
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.base.height_3d(x, y, z)
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, TaskPool},
+};
+
+use crate::terrain::resources::LakeSettings;
+
+use super::{
+    drainage::{DrainageGrid, Level},
+    GridSampling, HeightGrid, HeightSource,
+};
+
+const NO_LAKE: u32 = u32::MAX;
+
+/// Standing water filling a closed depression up to where it would spill.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lake {
+    /// Height of the water surface.
+    pub level: f32,
+    /// Depth at the deepest sample.
+    pub depth: f32,
+    /// Surface area in square world units, islands excluded.
+    pub area: f32,
+    /// Closed shoreline loops on the XZ plane, the outer shore and one per
+    /// island, with the water always on the same side.
+    pub outline: Vec<Vec<Vec2>>,
+    pub bounds: Rect,
+}
+
+/// The lakes of every sample of one tile.
+struct LakeTile {
+    lakes: Vec<u32>,
+    /// 1 under a lake and 0 elsewhere, for smooth proximity queries.
+    wet: HeightGrid,
+}
+
+/// Lakes found by flooding the terrain within [`LakeSettings::region`].
+///
+/// The region is split into square tiles that are flooded independently on
+/// the async compute pool, each treating its own edge as the outlet. Only the
+/// levels water spills between those edge samples at are carried over to a
+/// small graph joining the tiles, whose flood gives the final level of every
+/// edge sample once all tiles are in. So the result doesn't depend on the
+/// order the tiles finish in.
+#[derive(Default)]
+pub struct LakeMap {
+    /// Covered area, `region` grown to whole tiles.
+    pub region: Rect,
+    pub lakes: Vec<Lake>,
+    tile_size: f32,
+    cells: usize,
+    tiles: HashMap<IVec2, LakeTile>,
+}
+
+/// A tile flooded on its own, every edge sample acting as an outlet.
+struct TileFlood {
+    /// Global sample of the tile's first sample.
+    first: IVec2,
+    grid: DrainageGrid,
+    filled: Vec<f32>,
+    /// Global sample of the edge outlet every sample drains through.
+    outlets: Vec<IVec2>,
+    /// Lowest level at which water crosses between two outlets in this tile.
+    spills: HashMap<(IVec2, IVec2), f32>,
+}
+
+impl LakeMap {
+    /// Floods `source` over `settings.region`. Deterministic for a given
+    /// source and settings.
+    pub fn fill(source: &dyn HeightSource, settings: &LakeSettings) -> Self {
+        let cells = settings.resolution.max(2) as usize;
+        let tile_size = settings.tile_size.max(1.0);
+        let tile_count = (settings.region.size() / tile_size)
+            .ceil()
+            .max(Vec2::ONE)
+            .as_ivec2();
+        let region = Rect::from_corners(
+            settings.region.min,
+            settings.region.min + tile_count.as_vec2() * tile_size,
+        );
+        let cell = tile_size / cells as f32;
+
+        let mut map = Self {
+            region,
+            lakes: Vec::new(),
+            tile_size,
+            cells,
+            tiles: HashMap::new(),
+        };
+
+        // Outside of the app, as in the terrain CLI, there is no pool yet.
+        let thread_pool = AsyncComputeTaskPool::get_or_init(TaskPool::default);
+        let mut floods: Vec<TileFlood> = thread_pool.scope(|scope| {
+            let map = &map;
+            for j in 0..tile_count.y {
+                for i in 0..tile_count.x {
+                    scope.spawn(async move { map.flood_tile(source, IVec2::new(i, j), cell) });
+                }
+            }
+        });
+
+        let levels = outlet_levels(&floods, tile_count * cells as i32);
+        for flood in &mut floods {
+            for (filled, outlet) in flood.filled.iter_mut().zip(&flood.outlets) {
+                *filled = filled.max(levels[outlet]);
+            }
+        }
+
+        map.build_lakes(&floods, settings.min_depth, cell);
+        map
+    }
+
+    fn flood_tile(&self, source: &dyn HeightSource, tile: IVec2, cell: f32) -> TileFlood {
+        let size = self.cells + 1;
+        let first = tile * self.cells as i32;
+        let global =
+            |index: usize| first + IVec2::new((index % size) as i32, (index / size) as i32);
+
+        // Positions come from the global sample so tiles agree exactly on the
+        // heights along the edges they share.
+        let heights = (0..size * size)
+            .map(|index| {
+                let point = self.region.min + global(index).as_vec2() * cell;
+                source.height(point.x as f64, point.y as f64) as f32
+            })
+            .collect();
+        let grid = DrainageGrid {
+            size,
+            region: Rect::from_corners(
+                self.region.min + first.as_vec2() * cell,
+                self.region.min + (first + self.cells as i32).as_vec2() * cell,
+            ),
+            heights,
+        };
+
+        let mut filled = grid.heights.clone();
+        let mut outlets: Vec<Option<IVec2>> = vec![None; filled.len()];
+        let mut open = BinaryHeap::new();
+        for index in 0..filled.len() {
+            if grid.is_border(index) {
+                outlets[index] = Some(global(index));
+                open.push(Reverse((Level(filled[index]), index)));
+            }
+        }
+
+        let mut spills = HashMap::new();
+        while let Some(Reverse((Level(level), index))) = open.pop() {
+            let outlet = outlets[index].unwrap();
+
+            for (neighbour, _) in grid.neighbours(index) {
+                match outlets[neighbour] {
+                    None => {
+                        outlets[neighbour] = Some(outlet);
+                        filled[neighbour] = filled[neighbour].max(level);
+                        open.push(Reverse((Level(filled[neighbour]), neighbour)));
+                    }
+                    Some(other) if other != outlet => {
+                        let key = if outlet.to_array() < other.to_array() {
+                            (outlet, other)
+                        } else {
+                            (other, outlet)
+                        };
+                        let spill = level.max(filled[neighbour]);
+                        spills
+                            .entry(key)
+                            .and_modify(|current: &mut f32| *current = current.min(spill))
+                            .or_insert(spill);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        TileFlood {
+            first,
+            grid,
+            filled,
+            outlets: outlets.into_iter().map(Option::unwrap).collect(),
+            spills,
+        }
+    }
+
+    /// Labels the flooded samples of every tile, joins the labels across the
+    /// edges tiles share and keeps the lakes at least `min_depth` deep.
+    fn build_lakes(&mut self, floods: &[TileFlood], min_depth: f32, cell: f32) {
+        let size = self.cells + 1;
+
+        // Four-way connected flooded samples within each tile.
+        let mut components = Vec::with_capacity(floods.len());
+        let mut offsets = Vec::with_capacity(floods.len());
+        let mut total = 0;
+        for flood in floods {
+            let wet = |index: usize| flood.filled[index] > flood.grid.heights[index];
+            let mut labels = vec![usize::MAX; flood.filled.len()];
+            let mut count = 0;
+
+            for start in 0..labels.len() {
+                if !wet(start) || labels[start] != usize::MAX {
+                    continue;
+                }
+
+                labels[start] = count;
+                let mut stack = vec![start];
+                while let Some(index) = stack.pop() {
+                    let (i, j) = (index % size, index / size);
+                    let neighbours = [
+                        (i > 0).then(|| index - 1),
+                        (i + 1 < size).then(|| index + 1),
+                        (j > 0).then(|| index - size),
+                        (j + 1 < size).then(|| index + size),
+                    ];
+                    for neighbour in neighbours.into_iter().flatten() {
+                        if wet(neighbour) && labels[neighbour] == usize::MAX {
+                            labels[neighbour] = count;
+                            stack.push(neighbour);
+                        }
+                    }
+                }
+                count += 1;
+            }
+
+            offsets.push(total);
+            total += count;
+            components.push(labels);
+        }
+
+        // Samples on a shared edge are the same in both tiles, so their
+        // components are one lake.
+        let mut parents: Vec<usize> = (0..total).collect();
+        let mut edges: HashMap<IVec2, usize> = HashMap::new();
+        for (tile, flood) in floods.iter().enumerate() {
+            for (index, &label) in components[tile].iter().enumerate() {
+                if label == usize::MAX || !flood.grid.is_border(index) {
+                    continue;
+                }
+
+                let component = offsets[tile] + label;
+                let global = flood.first + IVec2::new((index % size) as i32, (index / size) as i32);
+                match edges.get(&global) {
+                    Some(&other) => union(&mut parents, component, other),
+                    None => {
+                        edges.insert(global, component);
+                    }
+                }
+            }
+        }
+
+        let mut depths = vec![0.0f32; total];
+        let mut levels = vec![f32::MIN; total];
+        for (tile, flood) in floods.iter().enumerate() {
+            for (index, &label) in components[tile].iter().enumerate() {
+                if label != usize::MAX {
+                    let root = find(&mut parents, offsets[tile] + label);
+                    depths[root] =
+                        depths[root].max(flood.filled[index] - flood.grid.heights[index]);
+                    levels[root] = levels[root].max(flood.filled[index]);
+                }
+            }
+        }
+
+        // Lake ids in order of first appearance, so they are stable.
+        let mut ids = vec![NO_LAKE; total];
+        for (tile, flood) in floods.iter().enumerate() {
+            let mut lakes = vec![NO_LAKE; flood.filled.len()];
+            for (index, &label) in components[tile].iter().enumerate() {
+                if label == usize::MAX {
+                    continue;
+                }
+
+                let root = find(&mut parents, offsets[tile] + label);
+                if depths[root] < min_depth {
+                    continue;
+                }
+
+                if ids[root] == NO_LAKE {
+                    ids[root] = self.lakes.len() as u32;
+                    self.lakes.push(Lake {
+                        level: levels[root],
+                        depth: depths[root],
+                        area: 0.0,
+                        outline: Vec::new(),
+                        bounds: Rect::default(),
+                    });
+                }
+                lakes[index] = ids[root];
+            }
+
+            if lakes.iter().all(|&lake| lake == NO_LAKE) {
+                continue;
+            }
+
+            let wet = lakes
+                .iter()
+                .map(|&lake| if lake == NO_LAKE { 0.0 } else { 1.0 })
+                .collect();
+            let tile_key = flood.first / self.cells as i32;
+            self.tiles.insert(
+                tile_key,
+                LakeTile {
+                    lakes,
+                    wet: HeightGrid::new(flood.grid.region, size, size, wet),
+                },
+            );
+        }
+
+        self.trace_outlines(floods, cell);
+    }
+
+    /// Marching squares over every tile, chaining the shoreline segments of
+    /// each lake into loops. Crossings are keyed by the pair of global samples
+    /// they lie between, so segments from neighbouring tiles join exactly.
+    fn trace_outlines(&mut self, floods: &[TileFlood], cell: f32) {
+        type Crossing = (IVec2, IVec2);
+
+        let size = self.cells + 1;
+        let mut segments: Vec<Vec<(Crossing, Crossing)>> = vec![Vec::new(); self.lakes.len()];
+        let mut positions: HashMap<Crossing, Vec2> = HashMap::new();
+
+        for flood in floods {
+            let Some(tile) = self.tiles.get(&(flood.first / self.cells as i32)) else {
+                continue;
+            };
+
+            for j in 0..self.cells {
+                for i in 0..self.cells {
+                    // Corners in the same rotational order for every cell, so
+                    // a shared edge is walked in opposite directions by the
+                    // two cells on either side of it.
+                    let corners =
+                        [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)].map(|(i, j)| j * size + i);
+                    let mut lakes = corners.map(|corner| tile.lakes[corner]);
+                    lakes.sort_unstable();
+
+                    for (k, &lake) in lakes.iter().enumerate() {
+                        if lake == NO_LAKE || (k > 0 && lakes[k - 1] == lake) {
+                            continue;
+                        }
+                        let level = self.lakes[lake as usize].level;
+                        let inside = corners.map(|corner| tile.lakes[corner] == lake);
+
+                        // Entering and leaving crossings alternate around the
+                        // cell, each leaving one is joined to the next one
+                        // entering.
+                        let mut crossings = Vec::new();
+                        for edge in 0..4 {
+                            let (a, b) = (corners[edge], corners[(edge + 1) % 4]);
+                            let (inside_a, inside_b) = (inside[edge], inside[(edge + 1) % 4]);
+                            if inside_a == inside_b {
+                                continue;
+                            }
+
+                            let global = |index: usize| {
+                                flood.first
+                                    + IVec2::new((index % size) as i32, (index / size) as i32)
+                            };
+                            let (ga, gb) = (global(a), global(b));
+                            let key = if ga.to_array() < gb.to_array() {
+                                (ga, gb)
+                            } else {
+                                (gb, ga)
+                            };
+
+                            let depth_a = level - flood.grid.heights[a];
+                            let depth_b = level - flood.grid.heights[b];
+                            let t = (depth_a / (depth_a - depth_b)).clamp(0.0, 1.0);
+                            let (pa, pb) = (
+                                self.region.min + ga.as_vec2() * cell,
+                                self.region.min + gb.as_vec2() * cell,
+                            );
+                            positions.entry(key).or_insert(pa + (pb - pa) * t);
+
+                            crossings.push((key, inside_a));
+                        }
+
+                        for (index, &(key, leaving)) in crossings.iter().enumerate() {
+                            if leaving {
+                                let next = crossings[(index + 1) % crossings.len()].0;
+                                segments[lake as usize].push((key, next));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (lake, segments) in self.lakes.iter_mut().zip(segments) {
+            let mut next: HashMap<Crossing, Crossing> = segments.iter().copied().collect();
+
+            for &(start, _) in &segments {
+                let mut outline = Vec::new();
+                let mut key = start;
+                while let Some(end) = next.remove(&key) {
+                    outline.push(positions[&key]);
+                    key = end;
+                }
+
+                if outline.len() >= 3 {
+                    lake.outline.push(outline);
+                }
+            }
+
+            let points = lake.outline.iter().flatten();
+            let (min, max) = points.fold((Vec2::MAX, Vec2::MIN), |(min, max), &point| {
+                (min.min(point), max.max(point))
+            });
+            lake.bounds = Rect { min, max };
+
+            let twice_area: f32 = lake
+                .outline
+                .iter()
+                .flat_map(|outline| {
+                    outline
+                        .iter()
+                        .zip(outline.iter().cycle().skip(1))
+                        .map(|(a, b)| a.x * b.y - b.x * a.y)
+                })
+                .sum();
+            lake.area = twice_area.abs() * 0.5;
+        }
+    }
+
+    /// The lake at or right next to `x`, `z`. Along the shore the ground may
+    /// already be above its level.
+    pub fn lake_at(&self, x: f32, z: f32) -> Option<&Lake> {
+        let point = Vec2::new(x, z);
+        if self.lakes.is_empty() || !self.region.contains(point) {
+            return None;
+        }
+
+        let tile_key = ((point - self.region.min) / self.tile_size)
+            .floor()
+            .as_ivec2();
+        let tile = self.tiles.get(&tile_key)?;
+
+        let cell = self.tile_size / self.cells as f32;
+        let tile_min = self.region.min + tile_key.as_vec2() * self.tile_size;
+        let grid = (point - tile_min) / cell;
+        let base = grid
+            .floor()
+            .as_uvec2()
+            .min(UVec2::splat(self.cells as u32 - 1));
+
+        let size = self.cells + 1;
+        [UVec2::ZERO, UVec2::X, UVec2::Y, UVec2::ONE]
+            .into_iter()
+            .map(|offset| base + offset)
+            .filter(|corner| tile.lakes[corner.y as usize * size + corner.x as usize] != NO_LAKE)
+            .min_by(|a, b| {
+                let a = a.as_vec2().distance_squared(grid);
+                let b = b.as_vec2().distance_squared(grid);
+                a.total_cmp(&b)
+            })
+            .map(|corner| {
+                let lake = tile.lakes[corner.y as usize * size + corner.x as usize];
+                &self.lakes[lake as usize]
+            })
+    }
+
+    /// Water level of the lake at or right next to `x`, `z`.
+    pub fn level(&self, x: f32, z: f32) -> Option<f32> {
+        self.lake_at(x, z).map(|lake| lake.level)
+    }
+
+    /// Fraction of the area around `x`, `z`, roughly `footprint` wide, that is
+    /// covered by lakes.
+    pub fn wetness(&self, x: f32, z: f32, footprint: f32) -> f32 {
+        let point = Vec2::new(x, z);
+        if self.lakes.is_empty() || !self.region.contains(point) {
+            return 0.0;
+        }
+
+        let tile_key = ((point - self.region.min) / self.tile_size)
+            .floor()
+            .as_ivec2();
+        self.tiles.get(&tile_key).map_or(0.0, |tile| {
+            tile.wet
+                .sample(x as f64, z as f64, footprint as f64, GridSampling::Bilinear)
+                as f32
+        })
+    }
+
+    /// Calls `f` with every grid cell that has a corner under a lake, and that
+    /// lake.
+    pub fn for_each_cell(&self, mut f: impl FnMut(Rect, &Lake)) {
+        let size = self.cells + 1;
+        let cell = self.tile_size / self.cells as f32;
+
+        let mut keys: Vec<_> = self.tiles.keys().copied().collect();
+        keys.sort_by_key(|key| (key.y, key.x));
+
+        for key in keys {
+            let tile = &self.tiles[&key];
+            let tile_min = self.region.min + key.as_vec2() * self.tile_size;
+
+            for j in 0..self.cells {
+                for i in 0..self.cells {
+                    let lake = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)]
+                        .into_iter()
+                        .map(|(i, j)| tile.lakes[j * size + i])
+                        .find(|&lake| lake != NO_LAKE);
+
+                    if let Some(lake) = lake {
+                        let min = tile_min + Vec2::new(i as f32, j as f32) * cell;
+                        f(
+                            Rect::from_corners(min, min + Vec2::splat(cell)),
+                            &self.lakes[lake as usize],
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Floods the graph of edge samples from the region's border and returns the
+/// level water stands at over every one of them.
+fn outlet_levels(floods: &[TileFlood], last: IVec2) -> HashMap<IVec2, f32> {
+    let mut graph: HashMap<IVec2, Vec<(IVec2, f32)>> = HashMap::new();
+    let mut open = BinaryHeap::new();
+
+    for flood in floods {
+        for (&(a, b), &spill) in &flood.spills {
+            graph.entry(a).or_default().push((b, spill));
+            graph.entry(b).or_default().push((a, spill));
+        }
+
+        let size = flood.grid.size;
+        for index in 0..flood.filled.len() {
+            let global = flood.first + IVec2::new((index % size) as i32, (index / size) as i32);
+            let outside =
+                global.x == 0 || global.y == 0 || global.x == last.x || global.y == last.y;
+            if outside {
+                let height = flood.grid.heights[index];
+                open.push(Reverse((Level(height), global.to_array())));
+            }
+        }
+    }
+
+    let mut levels = HashMap::new();
+    while let Some(Reverse((Level(level), node))) = open.pop() {
+        let node = IVec2::from(node);
+        if levels.contains_key(&node) {
+            continue;
+        }
+        levels.insert(node, level);
+
+        for &(next, spill) in graph.get(&node).into_iter().flatten() {
+            if !levels.contains_key(&next) {
+                open.push(Reverse((Level(level.max(spill)), next.to_array())));
+            }
+        }
+    }
+
+    // Every outlet is reachable through its own tile, this only guards
+    // against degenerate heights.
+    for flood in floods {
+        for (index, outlet) in flood.outlets.iter().enumerate() {
+            if flood.grid.is_border(index) {
+                levels.entry(*outlet).or_insert(flood.grid.heights[index]);
+            }
+        }
+    }
+
+    levels
+}
+
+fn find(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    if a != b {
+        parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGION: Rect = Rect {
+        min: Vec2::ZERO,
+        max: Vec2::splat(1024.0),
+    };
+
+    /// Cells along a tile of `tile_size`, keeping the sample spacing the same
+    /// whatever the tiling.
+    fn settings(tile_size: f32) -> LakeSettings {
+        LakeSettings {
+            enabled: true,
+            region: REGION,
+            tile_size,
+            resolution: (tile_size / 8.0) as u32,
+            min_depth: 1.0,
+        }
+    }
+
+    fn bump(distance: f32, width: f32) -> f32 {
+        (-(distance / width).powi(2)).exp()
+    }
+
+    /// A bowl around the centre of the region, which is a corner of four
+    /// tiles when they are a quarter of it, walled in by a ring well above
+    /// the ground outside.
+    fn basin(x: f64, z: f64) -> f64 {
+        let distance = Vec2::new(x as f32, z as f32).distance(REGION.center());
+        (40.0 * bump(distance - 250.0, 80.0) - 30.0 * bump(distance, 120.0)) as f64
+    }
+
+    /// Two bowls on either side of a tile edge, joined by a saddle lower than
+    /// the ring walling both in.
+    fn twin_basins(x: f64, z: f64) -> f64 {
+        let point = Vec2::new(x as f32, z as f32);
+        let center = REGION.center();
+        let depth = [center - Vec2::X * 160.0, center + Vec2::X * 160.0]
+            .map(|bowl| bump(point.distance(bowl), 110.0))
+            .into_iter()
+            .fold(0.0, f32::max);
+        let ring = bump(point.distance(center) - 400.0, 60.0);
+
+        (60.0 * ring - 30.0 * depth) as f64
+    }
+
+    #[test]
+    fn level_does_not_depend_on_tiling() {
+        let whole = LakeMap::fill(&basin, &settings(1024.0));
+        let tiled = LakeMap::fill(&basin, &settings(256.0));
+
+        assert_eq!(whole.lakes.len(), 1);
+        assert_eq!(tiled.lakes.len(), 1);
+        assert!(whole.lakes[0].level > 30.0);
+        assert!((whole.lakes[0].level - tiled.lakes[0].level).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fill_is_deterministic() {
+        let first = LakeMap::fill(&twin_basins, &settings(256.0));
+        let second = LakeMap::fill(&twin_basins, &settings(256.0));
+
+        assert!(!first.lakes.is_empty());
+        assert_eq!(first.lakes, second.lakes);
+    }
+
+    #[test]
+    fn basins_joined_through_saddle_share_one_level() {
+        let map = LakeMap::fill(&twin_basins, &settings(256.0));
+        let center = REGION.center();
+        let saddle = twin_basins(center.x as f64, center.y as f64) as f32;
+
+        assert_eq!(map.lakes.len(), 1);
+        assert!(map.lakes[0].level > saddle);
+
+        let left = map.level(center.x - 160.0, center.y);
+        let right = map.level(center.x + 160.0, center.y);
+        assert_eq!(left, Some(map.lakes[0].level));
+        assert_eq!(right, Some(map.lakes[0].level));
+    }
+
+    #[test]
+    fn no_lake_outside_region() {
+        let map = LakeMap::fill(&basin, &settings(256.0));
+        let center = REGION.center();
+
+        assert!(map.lake_at(center.x, center.y).is_some());
+        assert!(map.lake_at(-10.0, center.y).is_none());
+        assert!(map.lake_at(center.x, 1100.0).is_none());
+        assert!(map.lake_at(f32::MAX, f32::MAX).is_none());
+    }
+}
@@ -1,12 +1,122 @@
-use super::CHUNK_SIZE;
+use std::sync::Arc;
 
+use bevy::{
+    log::error,
+    tasks::{block_on, futures_lite::future},
+};
+
+use super::resources::GenerationSettings;
+
+mod biome;
 mod chunk;
+mod drainage;
+mod erosion;
+mod graph;
+mod grid;
+mod height;
+mod heightmap;
+mod lake;
+mod river;
+mod sculpt;
+pub use biome::*;
 pub use chunk::*;
+pub use erosion::*;
+pub use graph::*;
+pub use grid::*;
+pub use height::*;
+pub use heightmap::*;
+pub use lake::*;
+pub use river::*;
+pub use sculpt::*;
+
+/// Everything generated from one [`GenerationSettings`].
+pub struct TerrainSources {
+    pub height: Arc<dyn HeightSource>,
+    pub rivers: Arc<RiverNetwork>,
+    pub lakes: Arc<LakeMap>,
+}
+
+/// Builds the height source described by `settings`: the selected noise graph
+/// recipe, or the built-in fBm when there is none or it fails to load, under
+/// the imported heightmap if there is one, with erosion on top when it is
+/// enabled and the river valleys carved last.
+pub fn build_height_source(settings: &GenerationSettings) -> Arc<dyn HeightSource> {
+    build_terrain_sources(settings).height
+}
+
+/// Like [`build_height_source`], also returning the rivers traced over it and
+/// the lakes filling it.
+pub fn build_terrain_sources(settings: &GenerationSettings) -> TerrainSources {
+    block_on(build_terrain_sources_async(settings))
+}
+
+/// [`build_terrain_sources`] for the async compute pool. It yields before
+/// tracing the rivers and before filling the lakes, so dropping its task for
+/// a newer build stops it there instead of running every stage to the end.
+pub async fn build_terrain_sources_async(settings: &GenerationSettings) -> TerrainSources {
+    let mut source = build_base_height_source(settings);
+
+    if settings.erosion.hydraulic || settings.erosion.thermal.enabled {
+        source = Arc::new(ErodedHeightSource::new(
+            source,
+            &settings.erosion,
+            settings.seed,
+        ));
+    }
+
+    let mut rivers = Arc::default();
+    if settings.rivers.enabled {
+        future::yield_now().await;
+
+        let carved = RiverHeightSource::new(source, &settings.rivers);
+        rivers = carved.network().clone();
+        source = Arc::new(carved);
+    }
+
+    let lakes = if settings.lakes.enabled {
+        future::yield_now().await;
+
+        Arc::new(LakeMap::fill(source.as_ref(), &settings.lakes))
+    } else {
+        Arc::default()
+    };
+
+    TerrainSources {
+        height: source,
+        rivers,
+        lakes,
+    }
+}
+
+fn build_base_height_source(settings: &GenerationSettings) -> Arc<dyn HeightSource> {
+    let source = build_noise_source(settings);
+
+    if let Some(path) = &settings.heightmap.path {
+        match HeightmapSource::load(source.clone(), &settings.heightmap) {
+            Ok(heightmap) => return Arc::new(heightmap),
+            Err(err) => error!("{}: {err}", path.display()),
+        }
+    }
+
+    source
+}
+
+fn build_noise_source(settings: &GenerationSettings) -> Arc<dyn HeightSource> {
+    if let Some(path) = &settings.recipe {
+        match NoiseGraph::load(path).and_then(|graph| graph.compile(settings.seed)) {
+            Ok(graph) => return Arc::new(graph),
+            Err(err) => error!("{}: {err}", path.display()),
+        }
+    }
+
+    Arc::new(FbmHeightSource::new(settings.clone()))
+}
 
-pub fn chunks_for_radius(radius: i32, x: f32, z: f32) -> Vec<(i32, i32, f32)> {
+/// Chunks of `chunk_size` world units within `radius` chunks of `x`, `z`,
+/// with their squared distance in chunks.
+pub fn chunks_for_radius(radius: i32, x: f32, z: f32, chunk_size: f32) -> Vec<(i32, i32, f32)> {
     let mut chunks = Vec::new();
-    let center_chunk_x = (x / CHUNK_SIZE as f32).floor() as i32;
-    let center_chunk_z = (z / CHUNK_SIZE as f32).floor() as i32;
+    let (center_chunk_x, center_chunk_z) = global_to_chunk_position(x, z, chunk_size);
 
     for dx in -radius..=radius {
         for dz in -radius..=radius {
@@ -21,15 +131,15 @@ pub fn chunks_for_radius(radius: i32, x: f32, z: f32) -> Vec<(i32, i32, f32)> {
     chunks
 }
 
-pub fn chunk_to_global_position(x: i32, z: i32) -> (f32, f32) {
-    let x = (x * CHUNK_SIZE as i32) as f32 + CHUNK_SIZE as f32 / 2.0;
-    let z = (z * CHUNK_SIZE as i32) as f32 + CHUNK_SIZE as f32 / 2.0;
+pub fn chunk_to_global_position(x: i32, z: i32, chunk_size: f32) -> (f32, f32) {
+    let x = x as f32 * chunk_size + chunk_size / 2.0;
+    let z = z as f32 * chunk_size + chunk_size / 2.0;
     (x, z)
 }
 
-pub fn global_to_chunk_position(x: f32, z: f32) -> (i32, i32) {
-    let cx = (x / CHUNK_SIZE as f32).floor() as i32;
-    let cz = (z / CHUNK_SIZE as f32).floor() as i32;
+pub fn global_to_chunk_position(x: f32, z: f32, chunk_size: f32) -> (i32, i32) {
+    let cx = (x / chunk_size).floor() as i32;
+    let cz = (z / chunk_size).floor() as i32;
 
     (cx, cz)
 }
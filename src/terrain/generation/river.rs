@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::terrain::resources::RiverSettings;
+
+use super::{drainage::DrainageGrid, GridSampling, HeightGrid, HeightSource};
+
+/// A point along a [`River`].
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RiverPoint {
+    /// World position on the carved river bed.
+    pub position: Vec3,
+    /// Area draining through this point, in square world units.
+    pub flow: f32,
+    /// Width of the channel.
+    pub width: f32,
+}
+
+/// One stretch of river, from its source or a confluence down to where it
+/// joins another river or leaves the traced region. The points are the
+/// control points of a Catmull-Rom spline.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct River {
+    pub points: Vec<RiverPoint>,
+}
+
+impl River {
+    /// Position along the spline through the points, `t` going from 0 at the
+    /// first point to `points.len() - 1` at the last.
+    pub fn sample(&self, t: f32) -> Option<Vec3> {
+        let last = self.points.len().checked_sub(1)?;
+        let t = t.clamp(0.0, last as f32);
+        let segment = (t.floor() as usize).min(last.saturating_sub(1));
+        let local = t - segment as f32;
+
+        let point = |index: isize| self.points[index.clamp(0, last as isize) as usize].position;
+        let segment = segment as isize;
+        let [p0, p1, p2, p3] = [
+            point(segment - 1),
+            point(segment),
+            point(segment + 1),
+            point(segment + 2),
+        ];
+
+        let (t2, t3) = (local * local, local * local * local);
+        Some(
+            0.5 * (2.0 * p1
+                + (p2 - p0) * local
+                + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+        )
+    }
+}
+
+/// Every river traced over [`RiverSettings::region`], upstream stretches
+/// first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RiverNetwork {
+    pub region: Rect,
+    pub rivers: Vec<River>,
+}
+
+/// Traces the rivers of `source` within `settings.region` and returns them
+/// with how much each sample has to be lowered to carve their valleys.
+/// Deterministic for a given source.
+pub fn trace_rivers(
+    source: &dyn HeightSource,
+    settings: &RiverSettings,
+) -> (RiverNetwork, HeightGrid) {
+    let grid = DrainageGrid::sample(source, settings.region, settings.resolution as usize);
+    let cell = grid.cell_size();
+    let cell_area = cell.x * cell.y;
+
+    // Water has to leave closed basins somewhere, so flow is routed over the
+    // filled surface, where every cell has a lower neighbour until the border.
+    let filled = grid.priority_flood(1e-3);
+
+    let receivers: Vec<Option<usize>> = (0..filled.len())
+        .map(|index| {
+            if grid.is_border(index) {
+                return None;
+            }
+
+            let mut steepest = None;
+            let mut best = 0.0;
+            for (neighbour, distance) in grid.neighbours(index) {
+                let slope = (filled[index] - filled[neighbour]) / distance;
+                if slope > best {
+                    best = slope;
+                    steepest = Some(neighbour);
+                }
+            }
+
+            steepest
+        })
+        .collect();
+
+    // Every cell passes on its own rain plus everything above it, so cells
+    // are visited from the highest down.
+    let mut order: Vec<usize> = (0..filled.len()).collect();
+    order.sort_by(|&a, &b| filled[b].total_cmp(&filled[a]).then(a.cmp(&b)));
+
+    let mut accumulation = vec![1u32; filled.len()];
+    for &index in &order {
+        if let Some(receiver) = receivers[index] {
+            accumulation[receiver] += accumulation[index];
+        }
+    }
+
+    let threshold = settings.threshold.max(1);
+    let channel = |index: usize| accumulation[index] >= threshold;
+
+    let mut tributaries = vec![0u32; filled.len()];
+    for (index, receiver) in receivers.iter().enumerate() {
+        if let Some(&receiver) = receiver.as_ref().filter(|_| channel(index)) {
+            tributaries[receiver] += 1;
+        }
+    }
+
+    let width = |accumulation: u32| {
+        (settings.width * (accumulation as f32 / threshold as f32).sqrt()).min(settings.max_width)
+    };
+
+    // Walk down from every spring, stopping where a river that was already
+    // traced is met so each cell belongs to one stretch. Stretches ending in
+    // another join it at the point it already has there.
+    let mut traced: Vec<Option<RiverPoint>> = vec![None; filled.len()];
+    let mut rivers = Vec::new();
+    for spring in (0..filled.len()).filter(|&index| channel(index) && tributaries[index] == 0) {
+        let mut cells = vec![spring];
+        while let Some(next) = receivers[*cells.last().unwrap()] {
+            cells.push(next);
+            if traced[next].is_some() {
+                break;
+            }
+        }
+
+        let mut points: Vec<RiverPoint> = cells
+            .iter()
+            .map(|&index| {
+                // The filled surface always falls along the flow, so the bed
+                // never climbs. Across closed basins it stays at the level
+                // they spill over at, only cutting into the rim.
+                let position = grid.position(index);
+                let bed = filled[index] - settings.depth;
+                RiverPoint {
+                    position: Vec3::new(position.x, bed, position.y),
+                    flow: accumulation[index] as f32 * cell_area,
+                    width: width(accumulation[index]),
+                }
+            })
+            .collect();
+
+        smooth_course(&mut points);
+
+        let junction = traced[*cells.last().unwrap()];
+        if let (Some(junction), Some(last)) = (junction, points.last_mut()) {
+            *last = junction;
+        }
+
+        for (&index, point) in cells.iter().zip(&points) {
+            traced[index].get_or_insert(*point);
+        }
+
+        rivers.push(River { points });
+    }
+
+    let carve = carve_valleys(&grid, &rivers, settings);
+
+    (
+        RiverNetwork {
+            region: settings.region,
+            rivers,
+        },
+        carve,
+    )
+}
+
+/// Pulls every interior point of a course towards its neighbours, rounding off
+/// the 45 degree steps of the eight-way flow directions.
+fn smooth_course(points: &mut [RiverPoint]) {
+    for _ in 0..2 {
+        let previous: Vec<Vec3> = points.iter().map(|point| point.position).collect();
+        for index in 1..points.len().saturating_sub(1) {
+            let average = (previous[index - 1] + previous[index + 1]) * 0.5;
+            let smoothed = previous[index].lerp(average, 0.5);
+            points[index].position.x = smoothed.x;
+            points[index].position.z = smoothed.z;
+        }
+    }
+}
+
+/// How much to lower every sample of `grid` so the ground dips to each
+/// river's bed inside its channel and rises back to the untouched terrain
+/// across the valley on both sides.
+fn carve_valleys(grid: &DrainageGrid, rivers: &[River], settings: &RiverSettings) -> HeightGrid {
+    let size = grid.size;
+    let cell = grid.cell_size();
+    let mut carve = vec![0.0f32; size * size];
+
+    for river in rivers {
+        for pair in river.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let reach = a.width.max(b.width) * 0.5 + settings.valley_width;
+
+            let min = (a.position.xz().min(b.position.xz()) - reach - grid.region.min) / cell;
+            let max = (a.position.xz().max(b.position.xz()) + reach - grid.region.min) / cell;
+            let (first, last) = (
+                min.floor().max(Vec2::ZERO).as_uvec2(),
+                max.ceil().min(Vec2::splat((size - 1) as f32)).as_uvec2(),
+            );
+
+            let segment = b.position.xz() - a.position.xz();
+            let length = segment.length_squared().max(f32::EPSILON);
+
+            for j in first.y..=last.y {
+                for i in first.x..=last.x {
+                    let index = j as usize * size + i as usize;
+                    let point = grid.region.min + Vec2::new(i as f32, j as f32) * cell;
+
+                    let t = ((point - a.position.xz()).dot(segment) / length).clamp(0.0, 1.0);
+                    let distance = point.distance(a.position.xz() + segment * t);
+                    let half_width = (a.width + (b.width - a.width) * t) * 0.5;
+                    let bed = a.position.y + (b.position.y - a.position.y) * t;
+
+                    // 0 inside the channel, rising smoothly to 1 at the
+                    // valley's edge.
+                    let edge = ((distance - half_width) / settings.valley_width.max(f32::EPSILON))
+                        .clamp(0.0, 1.0);
+                    let shape = edge * edge * (3.0 - 2.0 * edge);
+
+                    let lowered = (bed - grid.heights[index]).min(0.0) * (1.0 - shape);
+                    carve[index] = carve[index].min(lowered);
+                }
+            }
+        }
+    }
+
+    // Fade out towards the border so the region blends into the untouched
+    // terrain around it, like erosion does.
+    let border = (size as f32 * 0.05).max(1.0);
+    for (index, value) in carve.iter_mut().enumerate() {
+        let (i, j) = ((index % size) as f32, (index / size) as f32);
+        let edge = i
+            .min(j)
+            .min((size - 1) as f32 - i)
+            .min((size - 1) as f32 - j);
+        *value *= (edge / border).clamp(0.0, 1.0);
+    }
+
+    HeightGrid::new(grid.region, size, size, carve)
+}
+
+/// Wraps a height source with the valleys of the rivers traced over it.
+pub struct RiverHeightSource {
+    source: Arc<dyn HeightSource>,
+    network: Arc<RiverNetwork>,
+    carve: HeightGrid,
+}
+
+impl RiverHeightSource {
+    pub fn new(source: Arc<dyn HeightSource>, settings: &RiverSettings) -> Self {
+        let (network, carve) = trace_rivers(source.as_ref(), settings);
+        Self {
+            source,
+            network: Arc::new(network),
+            carve,
+        }
+    }
+
+    pub fn network(&self) -> &Arc<RiverNetwork> {
+        &self.network
+    }
+}
+
+impl HeightSource for RiverHeightSource {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.height_filtered(x, z, 0.0)
+    }
+
+    fn height_filtered(&self, x: f64, z: f64, footprint: f64) -> f64 {
+        let height = self.source.height_filtered(x, z, footprint);
+        if !self.carve.contains(x, z) {
+            return height;
+        }
+
+        height + self.carve.sample(x, z, footprint, GridSampling::Bilinear)
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.source.height_3d(x, y, z)
+    }
+}
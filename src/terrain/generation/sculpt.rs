@@ -0,0 +1,274 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+
+use super::HeightSource;
+
+/// Samples along each side of a [`SculptLayer`] tile.
+pub const SCULPT_TILE_SAMPLES: i32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SculptTool {
+    Raise,
+    Lower,
+    /// Pulls every sample towards the average of its neighbours.
+    Smooth,
+    /// Pulls every sample towards the height under the brush's center.
+    Flatten,
+    /// Adds Perlin noise with a wavelength of about a fifth of the radius.
+    Noise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SculptBrush {
+    pub tool: SculptTool,
+    pub radius: f32,
+    /// World units per second for raise, lower and noise, and the fraction of
+    /// the way to the target per second for smooth and flatten.
+    pub strength: f32,
+    /// Fraction of the radius, from the rim inwards, over which the brush
+    /// fades out. Zero gives a hard edge.
+    pub falloff: f32,
+}
+
+impl Default for SculptBrush {
+    fn default() -> Self {
+        Self {
+            tool: SculptTool::Raise,
+            radius: 40.0,
+            strength: 20.0,
+            falloff: 0.5,
+        }
+    }
+}
+
+impl SculptBrush {
+    /// Influence at `distance` from the center, from 1 down to 0 at the rim.
+    pub fn weight(&self, distance: f32) -> f32 {
+        let t = distance / self.radius.max(f32::EPSILON);
+        if t >= 1.0 {
+            return 0.0;
+        }
+
+        let falloff = self.falloff.clamp(0.0, 1.0);
+        if falloff <= 0.0 {
+            return 1.0;
+        }
+
+        let t = ((t - (1.0 - falloff)) / falloff).clamp(0.0, 1.0);
+        1.0 - t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Sparse height offsets painted on top of the flat world, stored in square
+/// tiles that only exist where something was sculpted. Cheap to clone, tiles
+/// are shared until they are written to.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "SavedSculptLayer", into = "SavedSculptLayer")]
+pub struct SculptLayer {
+    /// World units between samples.
+    pub spacing: f32,
+    tiles: HashMap<IVec2, Arc<Vec<f32>>>,
+}
+
+impl Default for SculptLayer {
+    fn default() -> Self {
+        Self::new(2.0)
+    }
+}
+
+impl SculptLayer {
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            spacing,
+            tiles: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Removes every edit.
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    /// Offset at `x`, `z`, interpolated between samples.
+    pub fn height(&self, x: f32, z: f32) -> f32 {
+        if self.tiles.is_empty() {
+            return 0.0;
+        }
+
+        let grid = Vec2::new(x, z) / self.spacing;
+        let cell = grid.floor();
+        let (u, v) = (grid.x - cell.x, grid.y - cell.y);
+        let cell = cell.as_ivec2();
+
+        let top = self.sample(cell) * (1.0 - u) + self.sample(cell + IVec2::X) * u;
+        let bottom = self.sample(cell + IVec2::Y) * (1.0 - u) + self.sample(cell + IVec2::ONE) * u;
+        top * (1.0 - v) + bottom * v
+    }
+
+    fn sample(&self, sample: IVec2) -> f32 {
+        let (key, index) = locate(sample);
+        self.tiles.get(&key).map_or(0.0, |tile| tile[index])
+    }
+
+    fn sample_mut(&mut self, sample: IVec2) -> &mut f32 {
+        let (key, index) = locate(sample);
+        let tile = self.tiles.entry(key).or_insert_with(|| {
+            Arc::new(vec![
+                0.0;
+                (SCULPT_TILE_SAMPLES * SCULPT_TILE_SAMPLES) as usize
+            ])
+        });
+
+        &mut Arc::make_mut(tile)[index]
+    }
+
+    /// Applies `brush` at `center` for `delta` seconds. `base` is the height
+    /// underneath the layer, which smoothing and flattening work towards.
+    /// Returns the world rectangle whose heights changed.
+    pub fn apply(
+        &mut self,
+        brush: &SculptBrush,
+        center: Vec2,
+        delta: f32,
+        base: impl Fn(f32, f32) -> f32,
+    ) -> Rect {
+        let first = ((center - brush.radius) / self.spacing).floor().as_ivec2();
+        let last = ((center + brush.radius) / self.spacing).ceil().as_ivec2();
+        let height = |layer: &Self, sample: IVec2| {
+            let point = sample.as_vec2() * layer.spacing;
+            base(point.x, point.y) + layer.sample(sample)
+        };
+
+        let target = match brush.tool {
+            SculptTool::Flatten => base(center.x, center.y) + self.height(center.x, center.y),
+            _ => 0.0,
+        };
+        let noise = Perlin::new(0);
+        let wavelength = (brush.radius / 5.0).max(self.spacing);
+
+        let mut changes = Vec::new();
+        for j in first.y..=last.y {
+            for i in first.x..=last.x {
+                let sample = IVec2::new(i, j);
+                let point = sample.as_vec2() * self.spacing;
+                let weight = brush.weight(point.distance(center));
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                let amount = weight * brush.strength * delta;
+                let change = match brush.tool {
+                    SculptTool::Raise => amount,
+                    SculptTool::Lower => -amount,
+                    SculptTool::Smooth => {
+                        let average = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                            .iter()
+                            .map(|&offset| height(self, sample + offset))
+                            .sum::<f32>()
+                            / 4.0;
+                        (average - height(self, sample)) * amount.min(1.0)
+                    }
+                    SculptTool::Flatten => (target - height(self, sample)) * amount.min(1.0),
+                    SculptTool::Noise => {
+                        let point = point / wavelength;
+                        amount * noise.get([point.x as f64, point.y as f64]) as f32
+                    }
+                };
+
+                // Written after the loop so smoothing reads unmodified neighbours.
+                changes.push((sample, change));
+            }
+        }
+
+        for (sample, change) in changes {
+            *self.sample_mut(sample) += change;
+        }
+
+        // Vertices interpolate between samples, so one more on each side moves.
+        let margin = Vec2::splat(self.spacing);
+        Rect::from_corners(
+            first.as_vec2() * self.spacing - margin,
+            last.as_vec2() * self.spacing + margin,
+        )
+    }
+}
+
+/// A [`HeightSource`] with a [`SculptLayer`] added on top, for sampling the
+/// flat world the way its chunks show it outside of chunk generation.
+pub struct SculptedHeight {
+    pub source: Arc<dyn HeightSource>,
+    pub sculpt: Arc<SculptLayer>,
+}
+
+impl HeightSource for SculptedHeight {
+    fn height(&self, x: f64, z: f64) -> f64 {
+        self.source.height(x, z) + self.sculpt.height(x as f32, z as f32) as f64
+    }
+
+    fn height_filtered(&self, x: f64, z: f64, footprint: f64) -> f64 {
+        self.source.height_filtered(x, z, footprint) + self.sculpt.height(x as f32, z as f32) as f64
+    }
+
+    fn height_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        // Sculpting only applies to the flat world.
+        self.source.height_3d(x, y, z)
+    }
+}
+
+/// How a [`SculptLayer`] is written to disk, tiles sorted by key so saves of
+/// the same edits are identical.
+#[derive(Serialize, Deserialize)]
+struct SavedSculptLayer {
+    spacing: f32,
+    tiles: Vec<([i32; 2], Vec<f32>)>,
+}
+
+impl From<SculptLayer> for SavedSculptLayer {
+    fn from(layer: SculptLayer) -> Self {
+        let mut tiles: Vec<_> = layer
+            .tiles
+            .into_iter()
+            .map(|(key, tile)| (key.to_array(), tile.to_vec()))
+            .collect();
+        tiles.sort_by_key(|(key, _)| (key[1], key[0]));
+
+        Self {
+            spacing: layer.spacing,
+            tiles,
+        }
+    }
+}
+
+impl From<SavedSculptLayer> for SculptLayer {
+    fn from(saved: SavedSculptLayer) -> Self {
+        let size = (SCULPT_TILE_SAMPLES * SCULPT_TILE_SAMPLES) as usize;
+
+        Self {
+            spacing: saved.spacing,
+            tiles: saved
+                .tiles
+                .into_iter()
+                .filter(|(_, tile)| tile.len() == size)
+                .map(|(key, tile)| (IVec2::from(key), Arc::new(tile)))
+                .collect(),
+        }
+    }
+}
+
+/// Tile key and index within it of a sample.
+fn locate(sample: IVec2) -> (IVec2, usize) {
+    let key = IVec2::new(
+        sample.x.div_euclid(SCULPT_TILE_SAMPLES),
+        sample.y.div_euclid(SCULPT_TILE_SAMPLES),
+    );
+    let local = sample - key * SCULPT_TILE_SAMPLES;
+
+    (key, (local.y * SCULPT_TILE_SAMPLES + local.x) as usize)
+}
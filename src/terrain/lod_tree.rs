@@ -1,16 +1,36 @@
 use bevy::ecs::entity::Entity;
-use bevy_math::{Rect, Vec2};
+use bevy_math::{Rect, Vec2, Vec3, Vec3Swizzles};
 
-use super::resources::LODSettings;
+use super::{
+    planet::{CubeFace, PlanetSettings},
+    resources::LODSettings,
+};
 
 #[derive(Default, Clone, Debug)]
 pub struct LODTree {
     pub depth: usize,
     pub boundary: Rect,
     pub max_depth: usize,
+    /// Cube face this tree covers in planet mode, with `boundary` in face space.
+    pub face: Option<CubeFace>,
+    pub parent_boundary: Option<Rect>,
     pub leaf: LODLeaf,
 }
 
+/// Edge of a node's boundary. West and East are the min and max x sides,
+/// North and South the min and max y sides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    West,
+    East,
+    North,
+    South,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::West, Side::East, Side::North, Side::South];
+}
+
 #[derive(Default, Clone, Debug)]
 pub enum LODLeaf {
     Children(Box<[LODTree; 4]>),
@@ -20,20 +40,24 @@ pub enum LODLeaf {
 }
 
 impl LODTree {
-    pub fn new(max_depth: usize, boundary: Rect) -> Self {
+    pub fn new(max_depth: usize, boundary: Rect, face: Option<CubeFace>) -> Self {
         LODTree {
             depth: 0,
             max_depth,
             boundary,
+            face,
+            parent_boundary: None,
             leaf: LODLeaf::Pending,
         }
     }
 
-    fn new_child(boundary: Rect, max_depth: usize, depth: usize) -> Self {
+    fn new_child(&self, boundary: Rect) -> Self {
         Self {
             boundary,
-            depth,
-            max_depth,
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+            face: self.face,
+            parent_boundary: Some(self.boundary),
             leaf: LODLeaf::Pending,
         }
     }
@@ -49,23 +73,44 @@ impl LODTree {
 
         let rects = subdivide_rect(self.boundary);
         self.leaf = LODLeaf::Children(Box::new([
-            LODTree::new_child(rects.0, self.max_depth, self.depth + 1),
-            LODTree::new_child(rects.1, self.max_depth, self.depth + 1),
-            LODTree::new_child(rects.2, self.max_depth, self.depth + 1),
-            LODTree::new_child(rects.3, self.max_depth, self.depth + 1),
+            self.new_child(rects.0),
+            self.new_child(rects.1),
+            self.new_child(rects.2),
+            self.new_child(rects.3),
         ]));
 
-        return true;
+        true
+    }
+
+    /// Squared distance from the player to the center of this node. On a planet
+    /// face it is measured along the sphere surface.
+    pub fn distance_squared(&self, player: Vec3, planet: &PlanetSettings) -> f32 {
+        match self.face {
+            Some(face) => lod_distance_squared(
+                planet.surface_point(face, self.boundary.center()),
+                player,
+                Some(planet),
+            ),
+            None => self.boundary.center().distance_squared(player.xz()),
+        }
     }
 
-    pub fn should_collapse(&self, settings: &LODSettings, point: Vec2) -> bool {
-        let distance = f32::max(
-            settings.max + -(self.depth as f32) * settings.layer_penalty,
-            settings.min,
-        );
+    pub fn should_collapse(&self, settings: &LODSettings, distance_squared: f32) -> bool {
+        lod_ratio(
+            settings,
+            self.depth,
+            self.boundary.size().length(),
+            distance_squared,
+        ) < 1.0
+    }
 
-        self.boundary.center().distance_squared(point) / (self.boundary.size().length() * 100.0)
-            < distance
+    /// Where the chunk entity for this node is placed. Flat chunks are anchored
+    /// at their minimum corner, planet chunks at their center on the sphere.
+    pub fn chunk_origin(&self, planet: &PlanetSettings) -> Vec3 {
+        match self.face {
+            Some(face) => planet.surface_point(face, self.boundary.center()),
+            None => Vec3::new(self.boundary.min.x, 0.0, self.boundary.min.y),
+        }
     }
 
     pub fn can_collapse(&self) -> bool {
@@ -77,7 +122,7 @@ impl LODTree {
             return false;
         }
 
-        return true;
+        true
     }
 
     pub fn get_child_chunks_recursive(&self, out: &mut Vec<Entity>) {
@@ -88,11 +133,230 @@ impl LODTree {
                 }
             }
             LODLeaf::Chunk(entity) => {
-                out.push(entity.clone());
+                out.push(*entity);
             }
             LODLeaf::Pending => (),
         }
     }
+
+    pub fn is_leaf(&self) -> bool {
+        !matches!(self.leaf, LODLeaf::Children(_))
+    }
+
+    pub fn leaves<'a>(&'a self, out: &mut Vec<&'a LODTree>) {
+        match &self.leaf {
+            LODLeaf::Children(children) => {
+                for child in children.iter() {
+                    child.leaves(out);
+                }
+            }
+            _ => out.push(self),
+        }
+    }
+
+    /// The leaf containing `point`, if it lies within this tree.
+    pub fn find_leaf(&self, point: Vec2) -> Option<&LODTree> {
+        if !self.boundary.contains(point) {
+            return None;
+        }
+
+        match &self.leaf {
+            LODLeaf::Children(children) => children.iter().find_map(|child| child.find_leaf(point)),
+            _ => Some(self),
+        }
+    }
+
+    pub fn find_leaf_mut(&mut self, point: Vec2) -> Option<&mut LODTree> {
+        if !self.boundary.contains(point) {
+            return None;
+        }
+
+        if self.is_leaf() {
+            return Some(self);
+        }
+
+        match &mut self.leaf {
+            LODLeaf::Children(children) => children
+                .iter_mut()
+                .find_map(|child| child.find_leaf_mut(point)),
+            _ => None,
+        }
+    }
+
+    fn leaves_in<'a>(&'a self, rect: Rect, out: &mut Vec<&'a LODTree>) {
+        if self.boundary.intersect(rect).is_empty() {
+            return;
+        }
+
+        match &self.leaf {
+            LODLeaf::Children(children) => {
+                for child in children.iter() {
+                    child.leaves_in(rect, out);
+                }
+            }
+            _ => out.push(self),
+        }
+    }
+}
+
+/// Leaves of `trees` that share an edge with `rect` on `face`, on the given
+/// side. On a planet the side may lie on the face's edge, in which case the
+/// leaves come from the neighbouring face. Leaves that only touch a corner are
+/// not included.
+pub fn neighbours<'a>(
+    trees: &'a [LODTree],
+    face: Option<CubeFace>,
+    rect: Rect,
+    side: Side,
+    out: &mut Vec<&'a LODTree>,
+) {
+    let epsilon = rect.width().min(rect.height()) * 1e-3;
+    let strip = match side {
+        Side::West => Rect::new(
+            rect.min.x - epsilon,
+            rect.min.y + epsilon,
+            rect.min.x,
+            rect.max.y - epsilon,
+        ),
+        Side::East => Rect::new(
+            rect.max.x,
+            rect.min.y + epsilon,
+            rect.max.x + epsilon,
+            rect.max.y - epsilon,
+        ),
+        Side::North => Rect::new(
+            rect.min.x + epsilon,
+            rect.min.y - epsilon,
+            rect.max.x - epsilon,
+            rect.min.y,
+        ),
+        Side::South => Rect::new(
+            rect.min.x + epsilon,
+            rect.max.y,
+            rect.max.x - epsilon,
+            rect.max.y + epsilon,
+        ),
+    };
+
+    let Some(root) = trees.iter().find(|tree| tree.face == face) else {
+        return;
+    };
+    if root.boundary.contains(strip.center()) {
+        root.leaves_in(strip, out);
+        return;
+    }
+
+    // Past the edge of a face, the strip continues onto the face next to it.
+    // Along the shared edge both faces' coordinates agree up to sign, so the
+    // strip stays a thin rectangle there.
+    let Some(face) = face else {
+        return;
+    };
+    let half = root.boundary.half_size();
+    let direction = |point: Vec2| face.direction((point - root.boundary.center()) / half);
+    let (other, _) = CubeFace::from_direction(direction(strip.center()));
+    let Some(other_root) = trees.iter().find(|tree| tree.face == Some(other)) else {
+        return;
+    };
+
+    let corner = |point: Vec2| {
+        other
+            .project(direction(point))
+            .map(|uv| other_root.boundary.center() + uv * other_root.boundary.half_size())
+    };
+    if let (Some(a), Some(b)) = (corner(strip.min), corner(strip.max)) {
+        other_root.leaves_in(Rect::from_corners(a, b), out);
+    }
+}
+
+/// Depth of the deepest leaf of `trees` sharing an edge with `rect` on `face`.
+pub fn max_neighbour_depth(trees: &[LODTree], face: Option<CubeFace>, rect: Rect) -> Option<usize> {
+    let mut found = Vec::new();
+    for side in Side::ALL {
+        neighbours(trees, face, rect, side, &mut found);
+    }
+
+    found.iter().map(|neighbour| neighbour.depth).max()
+}
+
+/// Splits leaves until no two neighbouring leaves are more than one level
+/// apart, producing a restricted quadtree, across cube faces too in planet
+/// mode. Returns the chunks of the leaves that were split; new children are
+/// left pending.
+pub fn balance(trees: &mut [LODTree]) -> Vec<Entity> {
+    let mut removed = Vec::new();
+
+    loop {
+        let mut splits = Vec::new();
+        let mut leaves = Vec::new();
+        for tree in trees.iter() {
+            tree.leaves(&mut leaves);
+        }
+
+        for leaf in leaves {
+            let mut found = Vec::new();
+            for side in Side::ALL {
+                neighbours(trees, leaf.face, leaf.boundary, side, &mut found);
+            }
+
+            for neighbour in found {
+                if neighbour.depth + 1 < leaf.depth {
+                    splits.push((neighbour.face, neighbour.boundary.center(), neighbour.depth));
+                }
+            }
+        }
+
+        if splits.is_empty() {
+            return removed;
+        }
+
+        for (face, point, depth) in splits {
+            let Some(node) = trees
+                .iter_mut()
+                .find(|tree| tree.face == face)
+                .and_then(|tree| tree.find_leaf_mut(point))
+            else {
+                continue;
+            };
+
+            // Several leaves may have asked for the same split.
+            if node.depth != depth || !node.can_collapse() {
+                continue;
+            }
+
+            if let LODLeaf::Chunk(entity) = node.leaf {
+                removed.push(entity);
+            }
+            node.collapse();
+        }
+    }
+}
+
+/// Squared distance between a world position and the player, as used for LOD
+/// decisions: over the XZ plane, or along the surface of `planet`.
+pub fn lod_distance_squared(point: Vec3, player: Vec3, planet: Option<&PlanetSettings>) -> f32 {
+    let Some(planet) = planet else {
+        return point.xz().distance_squared(player.xz());
+    };
+
+    let distance =
+        (point - planet.center()).angle_between(player - planet.center()) * planet.radius;
+
+    if distance.is_nan() {
+        0.0
+    } else {
+        distance * distance
+    }
+}
+
+/// How far into its split range a node is: below one it should be split.
+pub fn lod_ratio(settings: &LODSettings, depth: usize, size: f32, distance_squared: f32) -> f32 {
+    let distance = f32::max(
+        settings.max + -(depth as f32) * settings.layer_penalty,
+        settings.min,
+    );
+
+    distance_squared / (size * 100.0) / distance
 }
 
 fn subdivide_rect(rect: Rect) -> (Rect, Rect, Rect, Rect) {
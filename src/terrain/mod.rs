@@ -1,75 +1,194 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use bevy::{prelude::*, render::render_resource::AsBindGroup};
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+    },
+    tasks::AsyncComputeTaskPool,
+};
 use bevy_egui::EguiContexts;
 use egui::{
     emath::RectTransform, Checkbox, CollapsingHeader, Color32, Frame, Pos2, Sense, Shape, Slider,
     Stroke,
 };
+use strum::IntoEnumIterator;
 
 use crate::{
     spectator::components::SpectatorCamera,
-    terrain::lod_tree::{LODLeaf, LODTree},
+    terrain::{
+        export::{
+            export_heightmap, export_meshes, export_rivers, generate_region_chunks,
+            HeightmapFormat, MeshFormat,
+        },
+        generation::{list_heightmaps, list_recipes, Biome, GridSampling, SculptTool},
+        lod_tree::{LODLeaf, LODTree},
+        planet::CubeFace,
+        save::{load_world, save_world, WorldSave},
+    },
 };
 
 use self::{
-    components::DeletedTerrainChunk,
-    resources::{Terrain, TerrainSettings},
+    components::{DeletedTerrainChunk, TerrainChunk},
+    resources::{
+        ColliderShape, SeamMode, SourceRebuild, Terrain, TerrainMode, TerrainSettings,
+        MAX_LOD_DEPTH,
+    },
 };
 
 pub mod components;
-mod generation;
+pub mod export;
+pub mod generation;
 mod lod_tree;
+pub mod planet;
+pub mod props;
+pub mod query;
 pub mod resources;
+pub mod save;
 mod systems;
-
-pub const CHUNK_SIZE: u32 = 4;
+pub mod vegetation;
+pub mod water;
 
 pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<TerrainMaterial>::default());
+        app.add_plugins(MaterialPlugin::<water::WaterMaterial>::default());
         app.init_resource::<resources::TerrainSettings>();
         app.init_resource::<resources::Terrain>();
+        app.init_resource::<resources::SourceRebuild>();
+        app.add_event::<components::ChunkMeshReady>();
 
         app.add_systems(PreUpdate, systems::update_lod_tree);
         app.add_systems(Update, systems::poll_pending_chunks);
         app.add_systems(Update, systems::process_marked_for_deletion);
+        app.add_systems(Update, systems::rebuild_terrain_sources.after(terrain_ui));
+        app.add_systems(
+            Update,
+            systems::update_chunk_colliders.after(systems::poll_pending_chunks),
+        );
+        app.add_systems(
+            Update,
+            systems::apply_geomorph.after(systems::poll_pending_chunks),
+        );
+
+        app.add_systems(
+            Update,
+            (systems::sculpt_terrain, systems::rebuild_edited_chunks).chain(),
+        );
+
+        app.add_systems(Startup, water::spawn_water);
+        app.add_systems(Update, water::update_water);
+        app.add_systems(
+            Update,
+            systems::update_underwater_chunks.after(systems::poll_pending_chunks),
+        );
+
+        app.add_plugins(vegetation::VegetationPlugin);
+        app.add_plugins(props::PropPlugin);
 
         app.add_systems(Update, terrain_ui);
     }
 }
 
 impl Material for TerrainMaterial {
-    fn fragment_shader() -> bevy::render::render_resource::ShaderRef {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/terrain/vertex.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
         "shaders/terrain/fragment.wgsl".into()
     }
 
     fn alpha_mode(&self) -> AlphaMode {
         self.alpha_mode
     }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+
+        Ok(())
+    }
 }
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+/// Colours terrain by height and slope: water below the sea level, then sand,
+/// grass, rock on steep slopes and snow on the peaks. Heights are measured
+/// from the planet's surface in planet mode.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, PartialEq)]
 pub struct TerrainMaterial {
     #[uniform(0)]
-    pub color: Color,
+    pub sea_level: f32,
+    /// Height above the sea level up to which the ground is sand.
+    #[uniform(0)]
+    pub sand_height: f32,
+    #[uniform(0)]
+    pub snow_height: f32,
+    /// Slope, from 0 when level to 1 when vertical, above which rock shows.
+    #[uniform(0)]
+    pub rock_slope: f32,
+    /// Width of the transitions between height layers, in world units.
+    #[uniform(0)]
+    pub height_blend: f32,
+    #[uniform(0)]
+    pub slope_blend: f32,
+    /// Planet center and radius, with a radius of zero for the flat world.
+    /// Kept in sync with [`TerrainSettings`] by the terrain UI.
+    #[uniform(0)]
+    pub planet: Vec4,
     pub alpha_mode: AlphaMode,
 }
 
+impl Default for TerrainMaterial {
+    fn default() -> Self {
+        Self {
+            sea_level: 20.0,
+            sand_height: 8.0,
+            snow_height: 380.0,
+            rock_slope: 0.3,
+            height_blend: 12.0,
+            slope_blend: 0.1,
+            planet: Vec4::ZERO,
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn terrain_ui(
     mut contexts: EguiContexts,
     mut terrain: ResMut<Terrain>,
     mut settings: ResMut<TerrainSettings>,
-    player: Query<&Transform, With<SpectatorCamera>>,
+    mut rebuild: ResMut<SourceRebuild>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut player: Query<&mut Transform, With<SpectatorCamera>>,
+    chunks: Query<(&TerrainChunk, &GlobalTransform), Without<DeletedTerrainChunk>>,
+    meshes: Res<Assets<Mesh>>,
     mut commands: Commands,
 ) {
-    let Ok(player) = player.get_single() else {
+    let Ok(mut player) = player.get_single_mut() else {
+        return;
+    };
+
+    let Some(mut material) = materials.get(&settings.material).cloned() else {
         return;
     };
 
     let mut regenerate = false;
+    let mut rebuild_source = false;
     egui::Window::new("Terrain").show(contexts.ctx_mut(), |ui| {
         CollapsingHeader::new("Information")
             .default_open(true)
@@ -80,12 +199,257 @@ fn terrain_ui(
                 {
                     regenerate = true;
                 }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .selectable_value(&mut settings.mode, TerrainMode::Flat, "Flat")
+                        .changed()
+                    {
+                        regenerate = true;
+                    }
+                    if ui
+                        .selectable_value(&mut settings.mode, TerrainMode::Planet, "Planet")
+                        .changed()
+                    {
+                        regenerate = true;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .selectable_value(&mut settings.seams.mode, SeamMode::None, "No Seams")
+                        .changed()
+                    {
+                        regenerate = true;
+                    }
+                    if ui
+                        .selectable_value(&mut settings.seams.mode, SeamMode::Skirts, "Skirts")
+                        .changed()
+                    {
+                        regenerate = true;
+                    }
+                });
+
+                if settings.seams.mode == SeamMode::Skirts
+                    && ui
+                        .add(
+                            Slider::new(&mut settings.seams.skirt_depth, 0.0..=1.0)
+                                .text("Skirt Depth"),
+                        )
+                        .changed()
+                {
+                    regenerate = true;
+                }
+
+                if ui
+                    .add(
+                        Slider::new(&mut settings.resolution.quads, 1..=64)
+                            .text("Chunk Resolution"),
+                    )
+                    .changed()
+                {
+                    regenerate = true;
+                }
+
+                let mut per_depth = !settings.resolution.per_depth.is_empty();
+                if ui
+                    .add(Checkbox::new(&mut per_depth, "Resolution Per Depth"))
+                    .changed()
+                {
+                    settings.resolution.per_depth = if per_depth {
+                        vec![settings.resolution.quads; MAX_LOD_DEPTH + 1]
+                    } else {
+                        Vec::new()
+                    };
+                    regenerate = true;
+                }
+
+                for (depth, quads) in settings.resolution.per_depth.iter_mut().enumerate() {
+                    if ui
+                        .add(Slider::new(quads, 1..=64).text(format!("Depth {depth}")))
+                        .changed()
+                    {
+                        regenerate = true;
+                    }
+                }
+
+                if settings.mode == TerrainMode::Flat {
+                    let biome = terrain.biome_at(player.translation.x, player.translation.z);
+                    ui.label(format!("Biome: {biome}"));
+
+                    if let Some(level) =
+                        terrain.lake_level(player.translation.x, player.translation.z)
+                    {
+                        ui.label(format!("Lake Level: {level:.1}"));
+                    }
+                }
+
+                if rebuild.is_busy() {
+                    ui.label("Rebuilding terrain...");
+                }
+
+                if settings.mode == TerrainMode::Planet
+                    && ui
+                        .add(
+                            Slider::new(&mut settings.planet.radius, 500.0..=50000.0)
+                                .text("Planet Radius")
+                                .logarithmic(true),
+                        )
+                        .changed()
+                {
+                    regenerate = true;
+                }
+            });
+
+        CollapsingHeader::new("Material")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.add(Slider::new(&mut material.sand_height, 0.0..=100.0).text("Sand Height"));
+                ui.add(Slider::new(&mut material.snow_height, 0.0..=1000.0).text("Snow Height"));
+                ui.add(Slider::new(&mut material.rock_slope, 0.0..=1.0).text("Rock Slope"));
+                ui.add(Slider::new(&mut material.height_blend, 0.0..=100.0).text("Height Blend"));
+                ui.add(Slider::new(&mut material.slope_blend, 0.0..=0.5).text("Slope Blend"));
+            });
+
+        CollapsingHeader::new("Water")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.add(Slider::new(&mut settings.sea_level, -100.0..=500.0).text("Sea Level"));
+
+                let water = &mut settings.water;
+                ui.add(Checkbox::new(&mut water.enabled, "Water"));
+                ui.add(Slider::new(&mut water.cell_size, 0.5..=64.0).text("Cell Size"));
+                ui.add(Slider::new(&mut water.cells, 4..=128).text("Cells Per Ring"));
+                ui.add(Slider::new(&mut water.rings, 1..=16).text("Rings"));
+            });
+
+        CollapsingHeader::new("Vegetation")
+            .default_open(false)
+            .show(ui, |ui| {
+                // Changes are picked up by the scatter systems, which place
+                // everything again without regenerating the chunks.
+                let vegetation = &mut settings.vegetation;
+                ui.add(Checkbox::new(&mut vegetation.enabled, "Vegetation"));
+                ui.add(
+                    Slider::new(&mut vegetation.max_instances, 100..=100000)
+                        .text("Max Instances")
+                        .logarithmic(true),
+                );
+
+                for rule in vegetation.rules.iter_mut() {
+                    CollapsingHeader::new(rule.kind.to_string()).show(ui, |ui| {
+                        ui.add(
+                            Slider::new(&mut rule.spacing, 0.5..=50.0)
+                                .text("Spacing")
+                                .logarithmic(true),
+                        );
+                        ui.add(
+                            Slider::new(&mut rule.min_depth, 0..=MAX_LOD_DEPTH).text("Min Depth"),
+                        );
+                        ui.add(
+                            Slider::new(&mut rule.full_depth, 0..=MAX_LOD_DEPTH).text("Full Depth"),
+                        );
+                        ui.add(Slider::new(&mut rule.thinning, 1.0..=3.0).text("Thinning"));
+                        ui.add(Slider::new(&mut rule.min_height, -50.0..=500.0).text("Min Height"));
+                        ui.add(Slider::new(&mut rule.max_height, 0.0..=1000.0).text("Max Height"));
+                        ui.add(Slider::new(&mut rule.max_slope, 0.0..=1.0).text("Max Slope"));
+                        ui.add(Slider::new(&mut rule.min_scale, 0.1..=5.0).text("Min Scale"));
+                        ui.add(Slider::new(&mut rule.max_scale, 0.1..=5.0).text("Max Scale"));
+                        rule.max_scale = rule.max_scale.max(rule.min_scale);
+
+                        let mut color = rule.color.to_array();
+                        ui.horizontal(|ui| {
+                            ui.color_edit_button_rgb(&mut color);
+                            ui.label("Color");
+                        });
+                        rule.color = Vec3::from_array(color);
+
+                        for (biome, density) in Biome::iter().zip(rule.density.iter_mut()) {
+                            ui.add(Slider::new(density, 0.0..=1.0).text(biome.to_string()));
+                        }
+                    });
+                }
+            });
+
+        CollapsingHeader::new("Props")
+            .default_open(false)
+            .show(ui, |ui| {
+                // Changes place every loaded region's props again.
+                let props = &mut settings.props;
+                ui.add(Checkbox::new(&mut props.enabled, "Props"));
+                ui.add(Slider::new(&mut props.region_size, 16.0..=256.0).text("Region Size"));
+                ui.add(Slider::new(&mut props.radius, 50.0..=1000.0).text("Radius"));
+                ui.add(Slider::new(&mut props.probe_step, 0.5..=16.0).text("Probe Step"));
+                ui.add(Slider::new(&mut props.max_props, 1..=1000).text("Max Props"));
+
+                for rule in props.rules.iter_mut() {
+                    CollapsingHeader::new(rule.kind.to_string()).show(ui, |ui| {
+                        ui.add(
+                            Slider::new(&mut rule.spacing, 1.0..=100.0)
+                                .text("Spacing")
+                                .logarithmic(true),
+                        );
+                        ui.add(Slider::new(&mut rule.density, 0.0..=1.0).text("Density"));
+                        ui.add(Slider::new(&mut rule.min_slope, 0.0..=1.0).text("Min Slope"));
+                        ui.add(Slider::new(&mut rule.max_slope, 0.0..=1.0).text("Max Slope"));
+                        ui.add(
+                            Slider::new(&mut rule.min_curvature, -1.0..=1.0).text("Min Curvature"),
+                        );
+                        ui.add(
+                            Slider::new(&mut rule.max_curvature, -1.0..=1.0).text("Max Curvature"),
+                        );
+                        ui.add(Slider::new(&mut rule.min_scale, 0.1..=10.0).text("Min Scale"));
+                        ui.add(Slider::new(&mut rule.max_scale, 0.1..=10.0).text("Max Scale"));
+                        rule.max_scale = rule.max_scale.max(rule.min_scale);
+                        ui.add(Slider::new(&mut rule.align, 0.0..=1.0).text("Align"));
+                        ui.add(Slider::new(&mut rule.sink, 0.0..=1.0).text("Sink"));
+                    });
+                }
             });
 
         CollapsingHeader::new("Generation Parameters")
             .default_open(false)
             .show(ui, |ui| {
                 let settings = &mut settings.generation;
+
+                let selected_recipe = settings
+                    .recipe
+                    .as_ref()
+                    .and_then(|path| path.file_stem())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "Built-in fBm".into());
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Recipe")
+                        .selected_text(selected_recipe)
+                        .show_ui(ui, |ui| {
+                            if ui
+                                .selectable_value(&mut settings.recipe, None, "Built-in fBm")
+                                .changed()
+                            {
+                                rebuild_source = true;
+                            }
+
+                            for path in list_recipes() {
+                                let name = path
+                                    .file_stem()
+                                    .map(|name| name.to_string_lossy().into_owned())
+                                    .unwrap_or_default();
+
+                                if ui
+                                    .selectable_value(&mut settings.recipe, Some(path), name)
+                                    .changed()
+                                {
+                                    rebuild_source = true;
+                                }
+                            }
+                        });
+
+                    if ui.button("Reload").clicked() {
+                        rebuild_source = true;
+                    }
+                });
+
                 if ui
                     .add(
                         Slider::new(&mut settings.seed, 0u32..=u32::MAX / 2)
@@ -94,7 +458,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -105,14 +469,14 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
                     .add(Slider::new(&mut settings.scale, 0.001..=1.0).text("Scale"))
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -123,7 +487,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -134,7 +498,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -145,7 +509,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -156,7 +520,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -167,7 +531,7 @@ fn terrain_ui(
                     )
                     .changed()
                 {
-                    regenerate = true;
+                    rebuild_source = true;
                 }
 
                 if ui
@@ -178,10 +542,626 @@ fn terrain_ui(
                     )
                     .changed()
                 {
+                    rebuild_source = true;
+                }
+
+                CollapsingHeader::new("Heightmap").show(ui, |ui| {
+                    let heightmap = &mut settings.heightmap;
+
+                    let selected = heightmap
+                        .path
+                        .as_ref()
+                        .and_then(|path| path.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "None".into());
+
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_label("File")
+                            .selected_text(selected)
+                            .show_ui(ui, |ui| {
+                                if ui
+                                    .selectable_value(&mut heightmap.path, None, "None")
+                                    .changed()
+                                {
+                                    rebuild_source = true;
+                                }
+
+                                for path in list_heightmaps() {
+                                    let name = path
+                                        .file_name()
+                                        .map(|name| name.to_string_lossy().into_owned())
+                                        .unwrap_or_default();
+
+                                    if ui
+                                        .selectable_value(&mut heightmap.path, Some(path), name)
+                                        .changed()
+                                    {
+                                        rebuild_source = true;
+                                    }
+                                }
+                            });
+
+                        if ui.button("Reload").clicked() {
+                            rebuild_source = true;
+                        }
+                    });
+
+                    let mut size = heightmap.extent.size();
+                    let mut changed = ui
+                        .add(
+                            Slider::new(&mut heightmap.extent.min.x, 0.0..=50000.0)
+                                .text("Extent X"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut heightmap.extent.min.y, 0.0..=50000.0)
+                                .text("Extent Z"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut size.x, 64.0..=50000.0)
+                                .text("Width")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut size.y, 64.0..=50000.0)
+                                .text("Depth")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    heightmap.extent.max = heightmap.extent.min + size;
+
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut heightmap.vertical_scale, 0.0..=4000.0)
+                                .text("Vertical Scale"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut heightmap.offset, -1000.0..=1000.0).text("Offset"))
+                        .changed();
+                    ui.horizontal(|ui| {
+                        changed |= ui
+                            .selectable_value(
+                                &mut heightmap.sampling,
+                                GridSampling::Bilinear,
+                                "Bilinear",
+                            )
+                            .changed();
+                        changed |= ui
+                            .selectable_value(
+                                &mut heightmap.sampling,
+                                GridSampling::Bicubic,
+                                "Bicubic",
+                            )
+                            .changed();
+                    });
+                    changed |= ui
+                        .add(Slider::new(&mut heightmap.noise_blend, 0.0..=1.0).text("Noise Blend"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut heightmap.falloff, 0.0..=4096.0).text("Falloff"))
+                        .changed();
+
+                    if changed && heightmap.path.is_some() {
+                        rebuild_source = true;
+                    }
+                });
+
+                CollapsingHeader::new("Erosion").show(ui, |ui| {
+                    let erosion = &mut settings.erosion;
+
+                    if ui
+                        .add(Checkbox::new(&mut erosion.hydraulic, "Hydraulic"))
+                        .changed()
+                    {
+                        rebuild_source = true;
+                    }
+
+                    let mut size = erosion.region.width();
+                    let mut changed = ui
+                        .add(Slider::new(&mut erosion.region.min.x, 0.0..=50000.0).text("Region X"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.region.min.y, 0.0..=50000.0).text("Region Z"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut size, 256.0..=16384.0)
+                                .text("Region Size")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    erosion.region.max = erosion.region.min + Vec2::splat(size);
+
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.resolution, 64..=2048).text("Resolution"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut erosion.droplets, 0..=1_000_000)
+                                .text("Rain")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.lifetime, 1..=128).text("Lifetime"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.inertia, 0.0..=1.0).text("Inertia"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.capacity, 0.0..=16.0).text("Capacity"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.min_slope, 0.0..=0.1).text("Min Slope"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.erosion, 0.0..=1.0).text("Erosion Rate"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut erosion.deposition, 0.0..=1.0).text("Deposition Rate"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.evaporation, 0.0..=0.5).text("Evaporation"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.gravity, 0.0..=16.0).text("Gravity"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.radius, 1..=8).text("Brush Radius"))
+                        .changed();
+
+                    if ui
+                        .add(Checkbox::new(&mut erosion.thermal.enabled, "Thermal"))
+                        .changed()
+                    {
+                        rebuild_source = true;
+                    }
+
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut erosion.thermal.iterations, 1..=500)
+                                .text("Thermal Iterations"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut erosion.thermal.talus_angle, 1.0..=89.0)
+                                .text("Talus Angle"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut erosion.thermal.rate, 0.0..=1.0).text("Thermal Rate"))
+                        .changed();
+
+                    if changed && (erosion.hydraulic || erosion.thermal.enabled) {
+                        rebuild_source = true;
+                    }
+                });
+
+                CollapsingHeader::new("Rivers").show(ui, |ui| {
+                    let rivers = &mut settings.rivers;
+
+                    if ui
+                        .add(Checkbox::new(&mut rivers.enabled, "Rivers"))
+                        .changed()
+                    {
+                        rebuild_source = true;
+                    }
+
+                    let mut size = rivers.region.width();
+                    let mut changed = ui
+                        .add(Slider::new(&mut rivers.region.min.x, 0.0..=50000.0).text("Region X"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut rivers.region.min.y, 0.0..=50000.0).text("Region Z"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut size, 256.0..=16384.0)
+                                .text("Region Size")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    rivers.region.max = rivers.region.min + Vec2::splat(size);
+
+                    changed |= ui
+                        .add(Slider::new(&mut rivers.resolution, 64..=2048).text("Resolution"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut rivers.threshold, 10..=10_000)
+                                .text("Threshold")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut rivers.width, 1.0..=100.0).text("Width"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut rivers.max_width, 1.0..=500.0).text("Max Width"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut rivers.depth, 0.0..=50.0).text("Depth"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut rivers.valley_width, 0.0..=500.0).text("Valley Width"),
+                        )
+                        .changed();
+
+                    if changed && rivers.enabled {
+                        rebuild_source = true;
+                    }
+                });
+
+                CollapsingHeader::new("Lakes").show(ui, |ui| {
+                    let lakes = &mut settings.lakes;
+
+                    if ui.add(Checkbox::new(&mut lakes.enabled, "Lakes")).changed() {
+                        rebuild_source = true;
+                    }
+
+                    let mut size = lakes.region.width();
+                    let mut changed = ui
+                        .add(Slider::new(&mut lakes.region.min.x, 0.0..=50000.0).text("Region X"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut lakes.region.min.y, 0.0..=50000.0).text("Region Z"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut size, 256.0..=16384.0)
+                                .text("Region Size")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    lakes.region.max = lakes.region.min + Vec2::splat(size);
+
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut lakes.tile_size, 256.0..=4096.0)
+                                .text("Tile Size")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut lakes.resolution, 16..=512).text("Resolution"))
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut lakes.min_depth, 0.0..=20.0).text("Min Depth"))
+                        .changed();
+
+                    if changed && lakes.enabled {
+                        rebuild_source = true;
+                    }
+                });
+
+                CollapsingHeader::new("Biomes").show(ui, |ui| {
+                    let biomes = &mut settings.biomes;
+
+                    let mut changed = ui
+                        .add(
+                            Slider::new(&mut biomes.climate_scale, 500.0..=50000.0)
+                                .text("Climate Scale")
+                                .logarithmic(true),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut biomes.lapse_rate, 0.0..=0.01).text("Lapse Rate"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut biomes.polar_cooling, 0.0..=1.0).text("Polar Cooling"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut biomes.blend, 0.01..=0.5).text("Blend"))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut biomes.lake_moisture, 0.0..=1.0).text("Lake Moisture"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(Slider::new(&mut biomes.lake_reach, 10.0..=2000.0).text("Lake Reach"))
+                        .changed();
+
+                    if changed {
+                        rebuild_source = true;
+                    }
+                });
+            });
+
+        CollapsingHeader::new("World")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut path = settings.save_path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    settings.save_path = path.into();
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        let save = WorldSave::capture(&settings, &terrain, &player);
+                        match save_world(&save, &settings.save_path) {
+                            Ok(()) => info!("saved world to {}", settings.save_path.display()),
+                            Err(err) => error!("{}: {err}", settings.save_path.display()),
+                        }
+                    }
+
+                    if ui.button("Load").clicked() {
+                        match load_world(&settings.save_path) {
+                            Ok(save) => {
+                                let mode = settings.mode;
+                                save.restore(&mut settings, &mut terrain, &mut player);
+                                // Chunks are regenerated once the rebuilt
+                                // sources are in. Only a new mode needs its own
+                                // LOD trees right away, and the old trees'
+                                // chunks go with them.
+                                if settings.mode != mode {
+                                    systems::regenerate_chunks(
+                                        &mut terrain,
+                                        &settings,
+                                        &mut commands,
+                                    );
+                                }
+                                rebuild_source = true;
+                            }
+                            Err(err) => error!("{}: {err}", settings.save_path.display()),
+                        }
+                    }
+                });
+            });
+
+        CollapsingHeader::new("Export")
+            .default_open(false)
+            .show(ui, |ui| {
+                let export = &mut settings.heightmap_export;
+
+                let mut path = export.path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    export.path = path.into();
+                }
+
+                ui.horizontal(|ui| {
+                    for (format, name) in [
+                        (HeightmapFormat::Png16, "PNG 16"),
+                        (HeightmapFormat::RawF32, "RAW f32"),
+                        (HeightmapFormat::Exr, "EXR"),
+                    ] {
+                        if ui
+                            .selectable_value(&mut export.format, format, name)
+                            .changed()
+                        {
+                            export.path.set_extension(format.extension());
+                        }
+                    }
+                });
+
+                let mut size = export.region.width();
+                ui.add(Slider::new(&mut export.region.min.x, 0.0..=50000.0).text("Region X"));
+                ui.add(Slider::new(&mut export.region.min.y, 0.0..=50000.0).text("Region Z"));
+                ui.add(
+                    Slider::new(&mut size, 64.0..=50000.0)
+                        .text("Region Size")
+                        .logarithmic(true),
+                );
+                export.region.max = export.region.min + Vec2::splat(size);
+
+                let mut resolution = export.resolution.x;
+                ui.add(
+                    Slider::new(&mut resolution, 2..=8192)
+                        .text("Resolution")
+                        .logarithmic(true),
+                );
+                export.resolution = UVec2::splat(resolution);
+
+                if ui.button("Export Heightmap").clicked() {
+                    let source = terrain.sculpted_source();
+                    let export = export.clone();
+
+                    AsyncComputeTaskPool::get()
+                        .spawn(async move {
+                            match export_heightmap(source.as_ref(), &export) {
+                                Ok(metadata) => info!(
+                                    "exported {}x{} heightmap to {}",
+                                    metadata.width,
+                                    metadata.height,
+                                    export.path.display()
+                                ),
+                                Err(err) => error!("{}: {err}", export.path.display()),
+                            }
+                        })
+                        .detach();
+                }
+
+                ui.separator();
+
+                let export = &mut settings.mesh_export;
+
+                let mut path = export.path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    export.path = path.into();
+                }
+
+                ui.horizontal(|ui| {
+                    for (format, name) in [(MeshFormat::Glb, "glTF"), (MeshFormat::Obj, "OBJ")] {
+                        if ui
+                            .selectable_value(&mut export.format, format, name)
+                            .changed()
+                        {
+                            export.path.set_extension(format.extension());
+                        }
+                    }
+                });
+
+                if ui.button("Export Loaded Chunks").clicked() {
+                    // Chunk meshes are scaled by a child entity, see `poll_pending_chunks`.
+                    let snapshot: Vec<(Mesh, GlobalTransform)> = chunks
+                        .iter()
+                        .filter_map(|(chunk, transform)| {
+                            let scale = Transform::from_scale(Vec3::new(chunk.1.x, 1.0, chunk.1.y));
+                            Some((
+                                meshes.get(&chunk.0)?.clone(),
+                                transform.mul_transform(scale),
+                            ))
+                        })
+                        .collect();
+                    let export = export.clone();
+
+                    AsyncComputeTaskPool::get()
+                        .spawn(async move {
+                            let meshes =
+                                snapshot.iter().map(|(mesh, transform)| (mesh, *transform));
+                            match export_meshes(meshes, export.format, &export.path) {
+                                Ok(()) => info!(
+                                    "exported {} chunks to {}",
+                                    snapshot.len(),
+                                    export.path.display()
+                                ),
+                                Err(err) => error!("{}: {err}", export.path.display()),
+                            }
+                        })
+                        .detach();
+                }
+
+                ui.add_enabled_ui(settings.mode == TerrainMode::Flat, |ui| {
+                    let export = &mut settings.mesh_export;
+
+                    let mut size = export.region.width();
+                    ui.add(Slider::new(&mut export.region.min.x, 0.0..=50000.0).text("Region X"));
+                    ui.add(Slider::new(&mut export.region.min.y, 0.0..=50000.0).text("Region Z"));
+                    ui.add(
+                        Slider::new(&mut size, 64.0..=50000.0)
+                            .text("Region Size")
+                            .logarithmic(true),
+                    );
+                    export.region.max = export.region.min + Vec2::splat(size);
+
+                    ui.add(Slider::new(&mut export.depth, 0..=MAX_LOD_DEPTH).text("Depth"));
+
+                    if ui.button("Export Region").clicked() {
+                        let source = terrain.sculpted_source();
+                        let biomes = terrain.biomes.clone();
+                        let export = export.clone();
+                        let chunk_size = settings.size / 2f32.powi(export.depth as i32);
+                        let resolution = settings.resolution.quads(export.depth);
+
+                        AsyncComputeTaskPool::get()
+                            .spawn(async move {
+                                let chunks = generate_region_chunks(
+                                    source,
+                                    biomes,
+                                    export.region,
+                                    chunk_size,
+                                    resolution,
+                                );
+                                let meshes = chunks.iter().map(|(mesh, transform)| {
+                                    (mesh, GlobalTransform::from(*transform))
+                                });
+
+                                match export_meshes(meshes, export.format, &export.path) {
+                                    Ok(()) => info!(
+                                        "exported {} chunks to {}",
+                                        chunks.len(),
+                                        export.path.display()
+                                    ),
+                                    Err(err) => error!("{}: {err}", export.path.display()),
+                                }
+                            })
+                            .detach();
+                    }
+                });
+
+                ui.separator();
+
+                let mut path = settings.river_export_path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    settings.river_export_path = path.into();
+                }
+
+                ui.add_enabled_ui(!terrain.rivers.rivers.is_empty(), |ui| {
+                    if ui.button("Export Rivers").clicked() {
+                        let rivers = terrain.rivers.clone();
+                        let path = settings.river_export_path.clone();
+
+                        AsyncComputeTaskPool::get()
+                            .spawn(async move {
+                                match export_rivers(&rivers, &path) {
+                                    Ok(()) => info!(
+                                        "exported {} rivers to {}",
+                                        rivers.rivers.len(),
+                                        path.display()
+                                    ),
+                                    Err(err) => error!("{}: {err}", path.display()),
+                                }
+                            })
+                            .detach();
+                    }
+                });
+            });
+
+        CollapsingHeader::new("Sculpt")
+            .default_open(false)
+            .show(ui, |ui| {
+                let sculpt = &mut settings.sculpt;
+
+                ui.add(Checkbox::new(&mut sculpt.enabled, "Sculpt (Left Mouse)"));
+                ui.horizontal(|ui| {
+                    for (tool, name) in [
+                        (SculptTool::Raise, "Raise"),
+                        (SculptTool::Lower, "Lower"),
+                        (SculptTool::Smooth, "Smooth"),
+                        (SculptTool::Flatten, "Flatten"),
+                        (SculptTool::Noise, "Noise"),
+                    ] {
+                        ui.selectable_value(&mut sculpt.brush.tool, tool, name);
+                    }
+                });
+                ui.add(
+                    Slider::new(&mut sculpt.brush.radius, 1.0..=1000.0)
+                        .text("Radius")
+                        .logarithmic(true),
+                );
+                ui.add(Slider::new(&mut sculpt.brush.strength, 0.0..=200.0).text("Strength"));
+                ui.add(Slider::new(&mut sculpt.brush.falloff, 0.0..=1.0).text("Falloff"));
+
+                if ui.button("Clear Edits").clicked() {
+                    Arc::make_mut(&mut terrain.sculpt).clear();
                     regenerate = true;
                 }
             });
 
+        CollapsingHeader::new("Physics")
+            .default_open(false)
+            .show(ui, |ui| {
+                let colliders = &mut settings.colliders;
+
+                ui.add(Checkbox::new(&mut colliders.enabled, "Colliders"));
+                ui.horizontal(|ui| {
+                    for (shape, name) in [
+                        (ColliderShape::Heightfield, "Heightfield"),
+                        (ColliderShape::Trimesh, "Trimesh"),
+                    ] {
+                        if ui
+                            .selectable_value(&mut colliders.shape, shape, name)
+                            .changed()
+                        {
+                            regenerate = true;
+                        }
+                    }
+                });
+                ui.add(Slider::new(&mut colliders.radius, 0.0..=2000.0).text("Actor Radius"));
+            });
+
         CollapsingHeader::new("LOD Tree")
             .default_open(true)
             .show(ui, |ui| {
@@ -197,6 +1177,9 @@ fn terrain_ui(
                         TimerMode::Repeating,
                     );
                 }
+                ui.add(Checkbox::new(&mut settings.lod.balanced, "2:1 Balanced"));
+                ui.add(Checkbox::new(&mut settings.lod.geomorph, "Geomorph"));
+                ui.add(Slider::new(&mut settings.lod.morph_start, 0.0..=0.99).text("Morph Start"));
                 ui.add(Slider::new(&mut settings.lod.max, 10.0..=2000.0).text("Max"));
                 ui.add(
                     Slider::new(&mut settings.lod.min, 0.0..=200.0)
@@ -256,40 +1239,82 @@ fn terrain_ui(
                             }
                         }
                     }
-                    let tree_rect = terrain.lod_tree.boundary;
-                    let tree_size = egui::Rect::from_min_size(
-                        Pos2::new(tree_rect.min.x, tree_rect.min.y),
-                        egui::Vec2::new(tree_rect.max.x, tree_rect.max.y),
-                    );
+                    // Planet faces are laid out side by side, three per row.
+                    let columns = terrain.lod_trees.len().min(3);
+                    let cell = 1.0 / columns as f32;
 
-                    let to_canvas = egui::emath::RectTransform::from_to(
-                        tree_size,
-                        to_screen.transform_rect(egui::Rect::from_min_size(
-                            Pos2::ZERO,
-                            egui::Vec2::new(1.0, 1.0),
-                        )),
-                    );
+                    let transforms: Vec<RectTransform> = terrain
+                        .lod_trees
+                        .iter()
+                        .enumerate()
+                        .map(|(index, tree)| {
+                            let tree_rect = tree.boundary;
+                            let tree_size = egui::Rect::from_min_max(
+                                Pos2::new(tree_rect.min.x, tree_rect.min.y),
+                                Pos2::new(tree_rect.max.x, tree_rect.max.y),
+                            );
+
+                            egui::emath::RectTransform::from_to(
+                                tree_size,
+                                to_screen.transform_rect(egui::Rect::from_min_size(
+                                    Pos2::new(
+                                        (index % columns) as f32 * cell,
+                                        (index / columns) as f32 * cell,
+                                    ),
+                                    egui::Vec2::splat(cell),
+                                )),
+                            )
+                        })
+                        .collect();
+
+                    for (tree, to_canvas) in terrain.lod_trees.iter().zip(&transforms) {
+                        draw_tree(tree, to_canvas, &painter);
+                    }
 
-                    draw_tree(&terrain.lod_tree, &to_canvas, &painter);
+                    let player_point = match settings.mode {
+                        TerrainMode::Flat => Some((0, player.translation.xz())),
+                        TerrainMode::Planet => {
+                            let (face, uv) = CubeFace::from_direction(
+                                player.translation - settings.planet.center(),
+                            );
 
-                    painter.extend(vec![Shape::circle_filled(
-                        to_canvas * Pos2::new(player.translation.x, player.translation.z),
-                        5.0,
-                        egui::Color32::from_rgb(0, 100, 255),
-                    )]);
+                            terrain
+                                .lod_trees
+                                .iter()
+                                .position(|tree| tree.face == Some(face))
+                                .map(|index| (index, uv * settings.planet.radius))
+                        }
+                    };
+
+                    if let Some((index, point)) = player_point {
+                        painter.extend(vec![Shape::circle_filled(
+                            transforms[index] * Pos2::new(point.x, point.y),
+                            5.0,
+                            egui::Color32::from_rgb(0, 100, 255),
+                        )]);
+                    }
 
                     response
                 });
             });
 
-        if regenerate {
-            let mut chunks = Vec::new();
-            terrain.lod_tree.get_child_chunks_recursive(&mut chunks);
-            for chunk in chunks {
-                commands.entity(chunk).insert(DeletedTerrainChunk);
-            }
+        material.sea_level = settings.sea_level;
+        material.planet = match settings.mode {
+            TerrainMode::Flat => Vec4::ZERO,
+            TerrainMode::Planet => settings.planet.center().extend(settings.planet.radius),
+        };
+
+        // Only touch the asset when something changed, as that re-uploads it.
+        if materials.get(&settings.material) != Some(&material) {
+            materials.insert(&settings.material, material);
+        }
 
-            terrain.lod_tree.leaf = LODLeaf::default();
+        if rebuild_source {
+            rebuild.request();
+        }
+
+        if regenerate {
+            systems::regenerate_chunks(&mut terrain, &settings, &mut commands);
         }
     });
 }
@@ -0,0 +1,111 @@
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanetSettings {
+    pub radius: f32,
+}
+
+impl PlanetSettings {
+    /// The planet sits below the origin so its +Y pole touches the flat world's ground plane.
+    pub fn center(&self) -> Vec3 {
+        Vec3::new(0.0, -self.radius, 0.0)
+    }
+
+    /// Bounds of every face's [`super::lod_tree::LODTree`]. Face space spans the
+    /// diameter so LOD distances stay in world-like units.
+    pub fn face_boundary(&self) -> Rect {
+        Rect::new(-self.radius, -self.radius, self.radius, self.radius)
+    }
+
+    /// Point on the undisplaced sphere for a position in face space.
+    pub fn surface_point(&self, face: CubeFace, position: Vec2) -> Vec3 {
+        self.center() + face.direction(position / self.radius) * self.radius
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PosX,
+        CubeFace::NegX,
+        CubeFace::PosY,
+        CubeFace::NegY,
+        CubeFace::PosZ,
+        CubeFace::NegZ,
+    ];
+
+    /// Normal of the face and the directions face space u and v run along.
+    /// `v × u` equals the normal, the same handedness as x/z on the flat grid,
+    /// so chunk triangles face outwards on every face.
+    pub fn axes(self) -> (Vec3, Vec3, Vec3) {
+        match self {
+            CubeFace::PosX => (Vec3::X, Vec3::Z, Vec3::Y),
+            CubeFace::NegX => (Vec3::NEG_X, Vec3::NEG_Z, Vec3::Y),
+            CubeFace::PosY => (Vec3::Y, Vec3::X, Vec3::Z),
+            CubeFace::NegY => (Vec3::NEG_Y, Vec3::X, Vec3::NEG_Z),
+            CubeFace::PosZ => (Vec3::Z, Vec3::X, Vec3::NEG_Y),
+            CubeFace::NegZ => (Vec3::NEG_Z, Vec3::X, Vec3::Y),
+        }
+    }
+
+    /// Unit direction for face coordinates in `[-1, 1]`. Uses the equal-angle
+    /// mapping, which keeps cells far more uniform than normalising the cube.
+    pub fn direction(self, uv: Vec2) -> Vec3 {
+        let (normal, u, v) = self.axes();
+        let warped = Vec2::new((uv.x * FRAC_PI_4).tan(), (uv.y * FRAC_PI_4).tan());
+
+        (normal + u * warped.x + v * warped.y).normalize()
+    }
+
+    /// Inverse of [`CubeFace::direction`].
+    pub fn from_direction(direction: Vec3) -> (CubeFace, Vec2) {
+        let abs = direction.abs();
+        let face = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x >= 0.0 {
+                CubeFace::PosX
+            } else {
+                CubeFace::NegX
+            }
+        } else if abs.y >= abs.z {
+            if direction.y >= 0.0 {
+                CubeFace::PosY
+            } else {
+                CubeFace::NegY
+            }
+        } else if direction.z >= 0.0 {
+            CubeFace::PosZ
+        } else {
+            CubeFace::NegZ
+        };
+
+        (face, face.project(direction).unwrap_or_default())
+    }
+
+    /// Face coordinates of `direction` continued past the face's edges, or
+    /// `None` when it points away from the face. Coordinates beyond `[-1, 1]`
+    /// lie on a neighbouring face.
+    pub fn project(self, direction: Vec3) -> Option<Vec2> {
+        let (normal, u, v) = self.axes();
+        let depth = direction.dot(normal);
+        if depth <= 0.0 {
+            return None;
+        }
+
+        Some(Vec2::new(
+            (direction.dot(u) / depth).atan() / FRAC_PI_4,
+            (direction.dot(v) / depth).atan() / FRAC_PI_4,
+        ))
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+use bevy_xpbd_3d::plugins::collision::Collider;
+
+use super::PropKind;
+
+/// Mesh and collider every prop of a [`PropKind`] shares, scaled by its
+/// transform.
+#[derive(Resource)]
+pub struct PropAssets {
+    shapes: Vec<(Handle<Mesh>, Collider)>,
+    pub material: Handle<StandardMaterial>,
+}
+
+impl PropAssets {
+    pub fn get(&self, kind: PropKind) -> &(Handle<Mesh>, Collider) {
+        &self.shapes[kind as usize]
+    }
+}
+
+impl FromWorld for PropAssets {
+    fn from_world(world: &mut World) -> Self {
+        // In the order of `PropKind`.
+        let shapes = [rock_mesh(), boulder_mesh(), cliff_mesh()]
+            .into_iter()
+            .map(|mesh| {
+                let collider = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+                    Some(VertexAttributeValues::Float32x3(positions)) => {
+                        Collider::convex_hull(positions.iter().map(|&p| Vec3::from(p)).collect())
+                    }
+                    _ => None,
+                };
+                let collider = collider.unwrap_or_else(|| Collider::sphere(0.5));
+
+                (world.resource_mut::<Assets<Mesh>>().add(mesh), collider)
+            })
+            .collect();
+
+        let material = world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial {
+                base_color: Color::rgb(0.42, 0.4, 0.38),
+                perceptual_roughness: 0.95,
+                ..Default::default()
+            });
+
+        Self { shapes, material }
+    }
+}
+
+/// Pushes every vertex in or out along its direction from the origin by a
+/// hash of its position, so vertices shared by several faces move together
+/// and the surface stays closed.
+fn roughen(mut mesh: Mesh, amount: f32, seed: u32) -> Mesh {
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions.iter_mut() {
+            let point = Vec3::from(*position);
+            let cell = (point * 1000.0).round().as_ivec3();
+            let hash =
+                cell.to_array()
+                    .iter()
+                    .fold(seed.wrapping_mul(0x9e37_79b9), |hash, &value| {
+                        (hash ^ value as u32)
+                            .wrapping_mul(0x85eb_ca6b)
+                            .rotate_left(13)
+                    });
+            let offset = (hash >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0;
+
+            *position = (point * (1.0 + offset * amount)).to_array();
+        }
+    }
+
+    // Faceted, so the roughness shows.
+    mesh.duplicate_vertices();
+    mesh.compute_flat_normals();
+    mesh
+}
+
+fn flatten(mut mesh: Mesh, scale: Vec3) -> Mesh {
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions.iter_mut() {
+            *position = (Vec3::from(*position) * scale).to_array();
+        }
+    }
+    mesh
+}
+
+/// A flattened stone about a unit across.
+fn rock_mesh() -> Mesh {
+    let mesh = Sphere::new(0.5)
+        .mesh()
+        .ico(1)
+        .expect("few enough subdivisions");
+    roughen(flatten(mesh, Vec3::new(1.0, 0.6, 0.8)), 0.25, 1)
+}
+
+/// A rounded block, also about a unit across before scaling.
+fn boulder_mesh() -> Mesh {
+    let mesh = Sphere::new(0.5)
+        .mesh()
+        .ico(2)
+        .expect("few enough subdivisions");
+    roughen(flatten(mesh, Vec3::new(1.0, 0.8, 0.9)), 0.15, 2)
+}
+
+/// A leaning slab of rock two units tall, meant to be half buried in steep
+/// ground.
+fn cliff_mesh() -> Mesh {
+    roughen(Mesh::from(Cuboid::new(1.2, 2.0, 0.7)), 0.2, 3)
+}
@@ -0,0 +1,385 @@
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+    utils::{HashMap, HashSet},
+};
+use bevy_xpbd_3d::components::RigidBody;
+use strum::{Display, EnumCount, EnumIter};
+
+use crate::spectator::components::SpectatorCamera;
+
+use super::{
+    components::PhysicsActor,
+    planet::CubeFace,
+    resources::{Terrain, TerrainMode, TerrainSettings},
+    systems::{rebuild_edited_chunks, sculpt_terrain},
+};
+
+pub use self::placement::*;
+
+mod meshes;
+mod placement;
+
+pub struct PropPlugin;
+
+impl Plugin for PropPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<meshes::PropAssets>();
+        app.init_resource::<PropRegions>();
+
+        // Between sculpting and the chunk rebuild, which clears the edited
+        // regions.
+        app.add_systems(
+            Update,
+            (update_prop_regions, poll_prop_regions)
+                .chain()
+                .after(sculpt_terrain)
+                .before(rebuild_edited_chunks),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumCount, EnumIter)]
+pub enum PropKind {
+    Rock,
+    Boulder,
+    /// Slabs jutting out of steep ground.
+    Cliff,
+}
+
+/// Where one [`PropKind`] is placed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropRule {
+    pub kind: PropKind,
+    /// Smallest distance between two props.
+    pub spacing: f32,
+    /// Chance for a sampled point to be kept before the ground rules apply.
+    pub density: f32,
+    /// Range of one minus the cosine of the ground's slope.
+    pub min_slope: f32,
+    pub max_slope: f32,
+    /// Range of the ground's curvature, positive in hollows and at the foot of
+    /// slopes, negative on ridges and edges.
+    pub min_curvature: f32,
+    pub max_curvature: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// How far the prop leans from straight up towards the ground's normal.
+    pub align: f32,
+    /// How deep the prop's origin is buried, relative to its scale.
+    pub sink: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropSettings {
+    pub enabled: bool,
+    /// Side of the square regions props are loaded in.
+    pub region_size: f32,
+    /// Distance from the player and physics actors within which regions are
+    /// loaded. They are unloaded a bit further out than that.
+    pub radius: f32,
+    /// Distance the ground is sampled at around a prop to get its slope and
+    /// curvature.
+    pub probe_step: f32,
+    /// Most props of one kind in a region.
+    pub max_props: usize,
+    pub rules: Vec<PropRule>,
+}
+
+impl Default for PropSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            region_size: 64.0,
+            radius: 300.0,
+            probe_step: 4.0,
+            max_props: 256,
+            rules: vec![
+                PropRule {
+                    kind: PropKind::Rock,
+                    spacing: 8.0,
+                    density: 0.3,
+                    min_slope: 0.02,
+                    max_slope: 0.45,
+                    min_curvature: -1.0,
+                    max_curvature: 1.0,
+                    min_scale: 0.4,
+                    max_scale: 1.2,
+                    align: 0.7,
+                    sink: 0.1,
+                },
+                PropRule {
+                    kind: PropKind::Boulder,
+                    spacing: 30.0,
+                    density: 0.6,
+                    min_slope: 0.0,
+                    max_slope: 0.3,
+                    min_curvature: 0.002,
+                    max_curvature: 1.0,
+                    min_scale: 1.5,
+                    max_scale: 4.0,
+                    align: 0.3,
+                    sink: 0.15,
+                },
+                PropRule {
+                    kind: PropKind::Cliff,
+                    spacing: 16.0,
+                    density: 0.8,
+                    min_slope: 0.35,
+                    max_slope: 1.0,
+                    min_curvature: -1.0,
+                    max_curvature: -0.002,
+                    min_scale: 2.0,
+                    max_scale: 5.0,
+                    align: 1.0,
+                    sink: 0.5,
+                },
+            ],
+        }
+    }
+}
+
+/// What the loaded props were placed for. Props are placed again from scratch
+/// whenever any of it changes.
+#[derive(Clone, PartialEq)]
+struct PlacementInputs {
+    height_source: usize,
+    mode: TerrainMode,
+    radius: f32,
+    seed: u32,
+    props: PropSettings,
+}
+
+/// Prop regions around the player, each an entity with its props as
+/// children.
+#[derive(Resource, Default)]
+pub struct PropRegions {
+    loaded: HashMap<PropRegionKey, Entity>,
+    inputs: Option<PlacementInputs>,
+    /// [`Terrain::edited`] as of the last update. Edits stay there while the
+    /// chunks under them are busy, and must only be handled once.
+    seen_edits: Vec<Rect>,
+}
+
+impl PropRegions {
+    pub fn get(&self, key: PropRegionKey) -> Option<Entity> {
+        self.loaded.get(&key).copied()
+    }
+}
+
+#[derive(Component)]
+pub struct PropRegion(pub PropRegionKey);
+
+#[derive(Component)]
+pub struct PendingPropRegion(Task<Vec<Prop>>);
+
+/// Loads the prop regions near the player and physics actors and unloads the
+/// ones left behind. Regions are a fixed grid independent of the LOD trees,
+/// so props stay put while chunks split and merge under them.
+fn update_prop_regions(
+    mut regions: ResMut<PropRegions>,
+    terrain: Res<Terrain>,
+    settings: Res<TerrainSettings>,
+    players: Query<&GlobalTransform, With<SpectatorCamera>>,
+    actors: Query<&GlobalTransform, With<PhysicsActor>>,
+    mut commands: Commands,
+) {
+    let props = &settings.props;
+    let inputs = PlacementInputs {
+        height_source: Arc::as_ptr(&terrain.height_source) as *const () as usize,
+        mode: settings.mode,
+        radius: settings.planet.radius,
+        seed: settings.generation.seed,
+        props: props.clone(),
+    };
+
+    let mut stale: HashSet<PropRegionKey> = HashSet::new();
+    if regions.inputs.as_ref() != Some(&inputs) {
+        stale.extend(regions.loaded.keys());
+        regions.inputs = Some(inputs);
+    } else if settings.mode == TerrainMode::Flat {
+        // Sculpting only touches the flat world.
+        for edited in terrain
+            .edited
+            .iter()
+            .filter(|edited| !regions.seen_edits.contains(edited))
+        {
+            stale.extend(
+                regions
+                    .loaded
+                    .keys()
+                    .filter(|key| !key.bounds(props.region_size).intersect(*edited).is_empty()),
+            );
+        }
+    }
+    regions.seen_edits.clone_from(&terrain.edited);
+
+    for key in stale {
+        if let Some(entity) = regions.loaded.remove(&key) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    if !props.enabled || props.region_size <= 0.0 {
+        return;
+    }
+
+    let planet = (settings.mode == TerrainMode::Planet).then_some(settings.planet);
+    let centers: Vec<Vec3> = players
+        .iter()
+        .chain(&actors)
+        .map(|transform| {
+            let position = transform.translation();
+            // Distances are measured along the ground, whatever the height.
+            match planet {
+                Some(planet) => {
+                    planet.center()
+                        + (position - planet.center()).normalize_or_zero() * planet.radius
+                }
+                None => Vec3::new(position.x, 0.0, position.z),
+            }
+        })
+        .collect();
+
+    let region_center = |key: PropRegionKey| match (planet, key.face) {
+        (Some(planet), Some(face)) => {
+            planet.surface_point(face, key.bounds(props.region_size).center())
+        }
+        _ => {
+            let center = key.bounds(props.region_size).center();
+            Vec3::new(center.x, 0.0, center.y)
+        }
+    };
+
+    // Half a region's diagonal, so regions partly in range count as in range.
+    let reach = props.region_size * std::f32::consts::FRAC_1_SQRT_2;
+    let distance = |key: PropRegionKey| {
+        let center = region_center(key);
+        centers
+            .iter()
+            .map(|point| point.distance(center))
+            .fold(f32::MAX, f32::min)
+            - reach
+    };
+
+    let far: Vec<PropRegionKey> = regions
+        .loaded
+        .keys()
+        .copied()
+        .filter(|&key| distance(key) > props.radius * 1.25)
+        .collect();
+    for key in far {
+        if let Some(entity) = regions.loaded.remove(&key) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    let mut wanted = HashSet::new();
+    for &center in &centers {
+        let faces: Vec<(Option<CubeFace>, Vec2)> = match planet {
+            Some(planet) => CubeFace::ALL
+                .iter()
+                .filter_map(|&face| {
+                    let uv = face.project(center - planet.center())?;
+                    Some((Some(face), uv * planet.radius))
+                })
+                .collect(),
+            None => vec![(None, center.xz())],
+        };
+
+        for (face, position) in faces {
+            let first = ((position - props.radius) / props.region_size)
+                .floor()
+                .as_ivec2();
+            let last = ((position + props.radius) / props.region_size)
+                .floor()
+                .as_ivec2();
+
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    let key = PropRegionKey {
+                        face,
+                        cell: IVec2::new(x, y),
+                    };
+
+                    // Regions wholly past the edge of their face belong to
+                    // the neighbouring face.
+                    let on_face = planet.is_none_or(|planet| {
+                        !key.bounds(props.region_size)
+                            .intersect(planet.face_boundary())
+                            .is_empty()
+                    });
+
+                    if on_face && distance(key) <= props.radius {
+                        wanted.insert(key);
+                    }
+                }
+            }
+        }
+    }
+
+    let thread_pool = AsyncComputeTaskPool::get();
+    for key in wanted {
+        if regions.loaded.contains_key(&key) {
+            continue;
+        }
+
+        let surface = PropSurface {
+            height: terrain.height_source.clone(),
+            sculpt: terrain.sculpt.clone(),
+            planet,
+        };
+        let props = props.clone();
+        let seed = settings.generation.seed;
+
+        let task = thread_pool.spawn(async move { place_props(&surface, key, &props, seed) });
+
+        let entity = commands
+            .spawn((
+                SpatialBundle::default(),
+                PropRegion(key),
+                PendingPropRegion(task),
+            ))
+            .id();
+        regions.loaded.insert(key, entity);
+    }
+}
+
+/// Spawns the props of every region that finished placing them, each with a
+/// static collider.
+fn poll_prop_regions(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut PendingPropRegion)>,
+    assets: Res<meshes::PropAssets>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(props) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .remove::<PendingPropRegion>()
+            .with_children(|region| {
+                for prop in props {
+                    let (mesh, collider) = assets.get(prop.kind);
+                    region.spawn((
+                        PbrBundle {
+                            mesh: mesh.clone(),
+                            material: assets.material.clone(),
+                            transform: Transform {
+                                translation: prop.position,
+                                rotation: prop.rotation,
+                                scale: Vec3::splat(prop.scale),
+                            },
+                            ..Default::default()
+                        },
+                        RigidBody::Static,
+                        collider.clone(),
+                    ));
+                }
+            });
+    }
+}
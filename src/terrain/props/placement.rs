@@ -0,0 +1,174 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::terrain::{
+    generation::{HeightSource, SculptLayer},
+    planet::{CubeFace, PlanetSettings},
+    vegetation::{hash_seed, poisson_disk},
+};
+
+use super::{PropKind, PropRule, PropSettings};
+
+/// A square of the fixed grid props are placed and loaded by, in flat world
+/// coordinates or in the face space of a cube face.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PropRegionKey {
+    pub face: Option<CubeFace>,
+    pub cell: IVec2,
+}
+
+impl PropRegionKey {
+    pub fn bounds(&self, size: f32) -> Rect {
+        let min = self.cell.as_vec2() * size;
+        Rect::from_corners(min, min + Vec2::splat(size))
+    }
+}
+
+/// One placed prop.
+#[derive(Clone, Copy, Debug)]
+pub struct Prop {
+    pub kind: PropKind,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+/// The generated ground, without any LOD, so props land on the same spot
+/// whichever chunks happen to be loaded.
+pub struct PropSurface {
+    pub height: Arc<dyn HeightSource>,
+    pub sculpt: Arc<SculptLayer>,
+    /// The planet in planet mode.
+    pub planet: Option<PlanetSettings>,
+}
+
+struct Probe {
+    point: Vec3,
+    up: Vec3,
+    normal: Vec3,
+    /// One minus the cosine of the slope.
+    slope: f32,
+    /// Laplacian of the height, positive in hollows and negative on ridges.
+    curvature: f32,
+}
+
+impl PropSurface {
+    /// World point of the ground and its height above the flat world's zero
+    /// or the planet's radius.
+    fn point(&self, face: Option<CubeFace>, position: Vec2) -> (Vec3, f32) {
+        match (self.planet, face) {
+            (Some(planet), Some(face)) => {
+                let direction = face.direction(position / planet.radius);
+                let surface = (direction * planet.radius).as_dvec3();
+                let height = self.height.height_3d(surface.x, surface.y, surface.z) as f32;
+
+                (
+                    planet.center() + direction * (planet.radius + height),
+                    height,
+                )
+            }
+            _ => {
+                let height = self.height.height(position.x as f64, position.y as f64) as f32
+                    + self.sculpt.height(position.x, position.y);
+
+                (Vec3::new(position.x, height, position.y), height)
+            }
+        }
+    }
+
+    /// Samples the ground around `position`, `step` apart along both axes.
+    fn probe(&self, face: Option<CubeFace>, position: Vec2, step: f32) -> Probe {
+        let (point, height) = self.point(face, position);
+        let [(east, east_height), (west, west_height), (north, north_height), (south, south_height)] =
+            [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y]
+                .map(|offset| self.point(face, position + offset * step));
+
+        let up = match self.planet {
+            Some(planet) => (point - planet.center()).normalize_or_zero(),
+            None => Vec3::Y,
+        };
+
+        // Pointed outwards, as the face axes aren't all the same handedness.
+        let normal = (north - south).cross(east - west).normalize_or_zero();
+        let normal = if normal.dot(up) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        Probe {
+            point,
+            up,
+            normal,
+            slope: 1.0 - normal.dot(up),
+            curvature: (east_height + west_height + north_height + south_height - 4.0 * height)
+                / (step * step),
+        }
+    }
+}
+
+/// Places the props of one region, the same every time for a world seed.
+pub fn place_props(
+    surface: &PropSurface,
+    key: PropRegionKey,
+    settings: &PropSettings,
+    seed: u32,
+) -> Vec<Prop> {
+    let bounds = key.bounds(settings.region_size);
+
+    // Regions along the edges of a cube face reach past it.
+    let face_boundary = surface.planet.map(|planet| planet.face_boundary());
+
+    let mut props = Vec::new();
+    for rule in &settings.rules {
+        let face = key.face.map_or(0, |face| face as u64 + 1);
+        let mut rng = StdRng::seed_from_u64(hash_seed([
+            seed as u64,
+            face,
+            key.cell.x as u64,
+            key.cell.y as u64,
+            rule.kind as u64,
+        ]));
+
+        for sample in poisson_disk(bounds.size(), rule.spacing, &mut rng, settings.max_props) {
+            // Drawn up front so one rejected point doesn't shift the values
+            // every following point gets.
+            let (chance, scale, yaw): (f32, f32, f32) = rng.gen();
+
+            let position = bounds.min + sample;
+            if face_boundary.is_some_and(|boundary| !boundary.contains(position)) {
+                continue;
+            }
+
+            if chance >= rule.density {
+                continue;
+            }
+
+            let probe = surface.probe(key.face, position, settings.probe_step);
+            if !rule.accepts(&probe) {
+                continue;
+            }
+
+            let scale = rule.min_scale + (rule.max_scale - rule.min_scale) * scale;
+            let axis = probe.up.lerp(probe.normal, rule.align).normalize_or_zero();
+
+            props.push(Prop {
+                kind: rule.kind,
+                position: probe.point - axis * rule.sink * scale,
+                rotation: Quat::from_rotation_arc(Vec3::Y, axis) * Quat::from_rotation_y(yaw * TAU),
+                scale,
+            });
+        }
+    }
+
+    props
+}
+
+impl PropRule {
+    fn accepts(&self, probe: &Probe) -> bool {
+        (self.min_slope..=self.max_slope).contains(&probe.slope)
+            && (self.min_curvature..=self.max_curvature).contains(&probe.curvature)
+    }
+}
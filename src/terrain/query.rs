@@ -0,0 +1,202 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{mesh::VertexAttributeValues, primitives::Aabb},
+};
+
+use super::{
+    components::{ChunkCollider, DeletedTerrainChunk, TerrainChunk},
+    resources::{Terrain, TerrainMode, TerrainSettings},
+};
+
+/// Where a ray met the rendered terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainHit {
+    pub point: Vec3,
+    /// Normal of the hit triangle, facing the ray's origin side of it.
+    pub normal: Vec3,
+    pub distance: f32,
+    pub chunk: Entity,
+}
+
+/// Height and ray queries against the terrain, for gameplay systems.
+///
+/// [`TerrainQuery::height`] samples the height source, so it is exact and
+/// works anywhere, loaded or not. The rendered variants intersect the meshes
+/// of the currently loaded chunks instead, and so match what is on screen,
+/// LOD and geomorphing included.
+#[derive(SystemParam)]
+pub struct TerrainQuery<'w, 's> {
+    terrain: Res<'w, Terrain>,
+    settings: Res<'w, TerrainSettings>,
+    meshes: Res<'w, Assets<Mesh>>,
+    chunks: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static TerrainChunk,
+            &'static ChunkCollider,
+            &'static Transform,
+        ),
+        Without<DeletedTerrainChunk>,
+    >,
+}
+
+impl<'w, 's> TerrainQuery<'w, 's> {
+    /// Height of the flat world's height source at `x`, `z`.
+    pub fn height(&self, x: f32, z: f32) -> f32 {
+        self.terrain.height_at(x, z)
+    }
+
+    /// Height of the loaded flat world surface at `x`, `z`, or `None` where
+    /// no chunk is loaded or in planet mode.
+    pub fn rendered_height(&self, x: f32, z: f32) -> Option<f32> {
+        if self.settings.mode != TerrainMode::Flat {
+            return None;
+        }
+
+        self.chunks
+            .iter()
+            .filter(|(_, _, collider, _)| {
+                let (min, max) = (collider.bounds.min(), collider.bounds.max());
+                (min.x..=max.x).contains(&x) && (min.z..=max.z).contains(&z)
+            })
+            .find_map(|(entity, chunk, collider, transform)| {
+                let origin = Vec3::new(x, collider.bounds.max().y + 1.0, z);
+                let ray = Ray3d::new(origin, Vec3::NEG_Y);
+                self.raycast_chunk(entity, chunk, transform, ray, f32::MAX)
+            })
+            .map(|hit| hit.point.y)
+    }
+
+    /// Closest intersection of `ray` with the loaded chunks within
+    /// `max_distance`, for picking and line of sight checks.
+    pub fn raycast(&self, ray: Ray3d, max_distance: f32) -> Option<TerrainHit> {
+        let mut closest: Option<TerrainHit> = None;
+
+        for (entity, chunk, collider, transform) in &self.chunks {
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+            if !ray_aabb(ray, &collider.bounds).is_some_and(|distance| distance <= limit) {
+                continue;
+            }
+
+            if let Some(hit) = self.raycast_chunk(entity, chunk, transform, ray, limit) {
+                closest = Some(hit);
+            }
+        }
+
+        closest
+    }
+
+    /// Whether nothing on the loaded terrain blocks the line from `from` to `to`.
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        let distance = from.distance(to);
+        if distance <= f32::EPSILON {
+            return true;
+        }
+
+        self.raycast(Ray3d::new(from, to - from), distance)
+            .is_none()
+    }
+
+    fn raycast_chunk(
+        &self,
+        entity: Entity,
+        chunk: &TerrainChunk,
+        transform: &Transform,
+        ray: Ray3d,
+        max_distance: f32,
+    ) -> Option<TerrainHit> {
+        let mesh = self.meshes.get(&chunk.0)?;
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+
+        // Same placement as the chunk's mesh child, see `poll_pending_chunks`.
+        let scale = Vec3::new(chunk.1.x, 1.0, chunk.1.y);
+        let vertex = |index: usize| transform.translation + Vec3::from(positions[index]) * scale;
+
+        let triangles: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let mut closest: Option<TerrainHit> = None;
+        for triangle in triangles.chunks_exact(3) {
+            let corners = [
+                vertex(triangle[0]),
+                vertex(triangle[1]),
+                vertex(triangle[2]),
+            ];
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+
+            let Some(distance) = ray_triangle(ray, corners) else {
+                continue;
+            };
+            if distance > limit {
+                continue;
+            }
+
+            let normal = (corners[1] - corners[0])
+                .cross(corners[2] - corners[0])
+                .normalize_or_zero();
+            let normal = if normal.dot(*ray.direction) > 0.0 {
+                -normal
+            } else {
+                normal
+            };
+
+            closest = Some(TerrainHit {
+                point: ray.get_point(distance),
+                normal,
+                distance,
+                chunk: entity,
+            });
+        }
+
+        closest
+    }
+}
+
+/// Distance along `ray` to where it enters `aabb`, zero if it starts inside.
+fn ray_aabb(ray: Ray3d, aabb: &Aabb) -> Option<f32> {
+    let inverse = ray.direction.recip();
+
+    let a = (Vec3::from(aabb.min()) - ray.origin) * inverse;
+    let b = (Vec3::from(aabb.max()) - ray.origin) * inverse;
+    let near = a.min(b).max_element().max(0.0);
+    let far = a.max(b).min_element();
+
+    (near <= far).then_some(near)
+}
+
+/// Möller-Trumbore, hitting triangles from either side.
+fn ray_triangle(ray: Ray3d, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let direction = *ray.direction;
+    let (ab, ac) = (b - a, c - a);
+
+    let p = direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let t = ray.origin - a;
+    let u = t.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = t.cross(ab);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = ac.dot(q) * inverse;
+    (distance >= 0.0).then_some(distance)
+}
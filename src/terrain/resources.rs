@@ -1,20 +1,152 @@
-use std::time::Duration;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::Task};
+use serde::{Deserialize, Serialize};
 
-use super::{lod_tree::LODTree, TerrainMaterial};
+use super::{
+    export::{HeightmapExport, MeshExport},
+    generation::{
+        build_terrain_sources, Biome, BiomeMap, GridSampling, HeightSource, LakeMap, RiverNetwork,
+        SculptBrush, SculptLayer, SculptedHeight, TerrainSources,
+    },
+    lod_tree::LODTree,
+    planet::{CubeFace, PlanetSettings},
+    props::PropSettings,
+    vegetation::VegetationSettings,
+    water::WaterSettings,
+    TerrainMaterial,
+};
 
 #[derive(Resource, Clone)]
 pub struct TerrainSettings {
     pub material: Handle<TerrainMaterial>,
     pub wireframe: bool,
+    pub mode: TerrainMode,
+    /// Extent of the flat world.
     pub size: Vec2,
+    pub planet: PlanetSettings,
+    pub seams: SeamSettings,
+    pub resolution: ResolutionSettings,
     pub generation: GenerationSettings,
     pub lod: LODSettings,
+    pub colliders: ColliderSettings,
+    pub sculpt: SculptSettings,
+    /// Height of the ocean surface, above the planet's radius in planet mode.
+    pub sea_level: f32,
+    pub water: WaterSettings,
+    pub vegetation: VegetationSettings,
+    pub props: PropSettings,
+    /// What the terrain UI's heightmap export writes.
+    pub heightmap_export: HeightmapExport,
+    /// What the terrain UI's mesh export writes.
+    pub mesh_export: MeshExport,
+    /// Where the terrain UI writes the river splines.
+    pub river_export_path: PathBuf,
+    /// World file the terrain UI saves to and loads from.
+    pub save_path: PathBuf,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerrainMode {
+    Flat,
+    /// A cube-sphere of six LOD trees, see [`PlanetSettings`].
+    Planet,
+}
+
+#[derive(Clone, Copy)]
+pub struct SeamSettings {
+    pub mode: SeamMode,
+    /// Skirt length as a fraction of the chunk's width, so coarse chunks, whose
+    /// edges can be further off from their neighbours, get longer skirts.
+    pub skirt_depth: f32,
+}
+
+impl Default for SeamSettings {
+    fn default() -> Self {
+        Self {
+            mode: SeamMode::Skirts,
+            skirt_depth: 0.1,
+        }
+    }
+}
+
+/// Deepest level any [`LODTree`] is split to.
+pub const MAX_LOD_DEPTH: usize = 12;
+
+/// [`TerrainSettings::sea_level`] of a new world.
+pub const DEFAULT_SEA_LEVEL: f32 = 20.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResolutionSettings {
+    /// Quads along each side of a chunk.
+    pub quads: u32,
+    /// Overrides `quads` for chunks at the LOD depth of each entry, so near
+    /// chunks can be denser than far ones. Depths past the end use `quads`.
+    pub per_depth: Vec<u32>,
+}
+
+impl ResolutionSettings {
+    pub fn quads(&self, depth: usize) -> u32 {
+        self.per_depth.get(depth).copied().unwrap_or(self.quads)
+    }
+}
+
+impl Default for ResolutionSettings {
+    fn default() -> Self {
+        Self {
+            quads: 4,
+            per_depth: Vec::new(),
+        }
+    }
+}
+
+/// Static colliders for the chunks around [`super::components::PhysicsActor`]s.
+#[derive(Clone, Copy)]
+pub struct ColliderSettings {
+    pub enabled: bool,
+    pub shape: ColliderShape,
+    /// Distance from an actor within which chunks get colliders, or zero to
+    /// give every chunk one.
+    pub radius: f32,
+}
+
+impl Default for ColliderSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            shape: ColliderShape::Heightfield,
+            radius: 250.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColliderShape {
+    /// Cheaper, and splits every quad along the same diagonal as the mesh.
+    /// Planet chunks always use trimeshes.
+    Heightfield,
+    Trimesh,
+}
+
+/// Brush painted onto [`Terrain::sculpt`] while the left mouse button is held.
+#[derive(Clone, Copy, Default)]
+pub struct SculptSettings {
+    pub enabled: bool,
+    pub brush: SculptBrush,
+}
+
+/// How cracks between neighbouring chunks at different LOD depths are hidden.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeamMode {
+    None,
+    Skirts,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GenerationSettings {
+    /// Noise graph recipe to generate from instead of the fBm parameters below.
+    pub recipe: Option<PathBuf>,
     pub seed: u32,
     pub amplitude: f64,
     pub scale: f32,
@@ -24,14 +156,256 @@ pub struct GenerationSettings {
     pub frequency: f64,
     pub exponentiation: f64,
     pub height: f64,
+    pub heightmap: HeightmapSettings,
+    pub erosion: ErosionSettings,
+    pub rivers: RiverSettings,
+    pub lakes: LakeSettings,
+    pub biomes: BiomeSettings,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        Self {
+            recipe: None,
+            seed: 100,
+            amplitude: 0.01,
+            scale: 0.005,
+            octaves: 16,
+            lacunarity: 1.7,
+            persistence: 0.7,
+            frequency: 0.11,
+            exponentiation: 0.81,
+            height: 550.0,
+            heightmap: HeightmapSettings::default(),
+            erosion: ErosionSettings::default(),
+            rivers: RiverSettings::default(),
+            lakes: LakeSettings::default(),
+            biomes: BiomeSettings::default(),
+        }
+    }
+}
+
+/// An imported heightmap used as the base layer of the flat world, see
+/// [`crate::terrain::generation::load_heightmap`] for the supported formats.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightmapSettings {
+    pub path: Option<PathBuf>,
+    /// World rectangle the heightmap covers.
+    pub extent: Rect,
+    /// Height of a white pixel above a black one, in world units.
+    pub vertical_scale: f32,
+    /// Height of a black pixel.
+    pub offset: f32,
+    pub sampling: GridSampling,
+    /// Share of the procedural terrain added on top of the heightmap.
+    pub noise_blend: f32,
+    /// Distance outside the extent over which the heightmap fades into the
+    /// procedural terrain.
+    pub falloff: f32,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            path: None,
+            extent: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            vertical_scale: 500.0,
+            offset: 0.0,
+            sampling: GridSampling::Bicubic,
+            noise_blend: 0.0,
+            falloff: 512.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeSettings {
+    /// Rough size of climate regions, in world units.
+    pub climate_scale: f32,
+    /// Temperature lost per world unit of altitude.
+    pub lapse_rate: f32,
+    /// Temperature lost at the poles in planet mode.
+    pub polar_cooling: f32,
+    /// How far apart in climate neighbouring biomes blend.
+    pub blend: f32,
+    /// Moisture added on the shores of lakes.
+    pub lake_moisture: f32,
+    /// Distance from a lake over which its shore is moister.
+    pub lake_reach: f32,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        Self {
+            climate_scale: 6000.0,
+            lapse_rate: 0.0015,
+            polar_cooling: 0.6,
+            blend: 0.05,
+            lake_moisture: 0.3,
+            lake_reach: 200.0,
+        }
+    }
+}
+
+/// Erosion stages, simulated once over `region` whenever the height source is
+/// rebuilt. Only affects the flat world.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionSettings {
+    /// Run the droplet hydraulic erosion stage.
+    pub hydraulic: bool,
+    pub region: Rect,
+    /// Samples along the longer side of the simulated grid, whose cells are
+    /// square.
+    pub resolution: u32,
+    /// Number of rain droplets.
+    pub droplets: u32,
+    /// Steps a droplet lives for.
+    pub lifetime: u32,
+    /// How much a droplet keeps its direction instead of following the slope.
+    pub inertia: f32,
+    /// Sediment a droplet can carry, relative to its speed and water.
+    pub capacity: f32,
+    /// Lowest slope used for the carry capacity, so flat ground still erodes.
+    pub min_slope: f32,
+    /// Fraction of the free capacity picked up each step.
+    pub erosion: f32,
+    /// Fraction of the excess sediment dropped each step.
+    pub deposition: f32,
+    /// Fraction of water lost each step.
+    pub evaporation: f32,
+    pub gravity: f32,
+    /// Radius in cells over which erosion is spread.
+    pub radius: u32,
+    pub thermal: ThermalSettings,
+}
+
+/// Thermal erosion: material slides downhill wherever the slope is steeper than
+/// the talus angle. Runs after the hydraulic stage.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalSettings {
+    pub enabled: bool,
+    pub iterations: u32,
+    /// Steepest stable slope, in degrees.
+    pub talus_angle: f32,
+    /// Fraction of the excess material moved each iteration.
+    pub rate: f32,
+}
+
+impl Default for ThermalSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iterations: 50,
+            talus_angle: 35.0,
+            rate: 0.5,
+        }
+    }
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            hydraulic: false,
+            region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            resolution: 512,
+            droplets: 70_000,
+            lifetime: 30,
+            inertia: 0.05,
+            capacity: 4.0,
+            min_slope: 0.01,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+            gravity: 4.0,
+            radius: 3,
+            thermal: ThermalSettings::default(),
+        }
+    }
+}
+
+/// Rivers traced from flow accumulation over `region` whenever the height
+/// source is rebuilt, after erosion. Only affects the flat world.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiverSettings {
+    pub enabled: bool,
+    pub region: Rect,
+    /// Samples along each side of the drainage grid.
+    pub resolution: u32,
+    /// Cells that have to drain through a cell for it to carry a river.
+    pub threshold: u32,
+    /// Channel width where a river starts, growing with the square root of
+    /// the area it drains.
+    pub width: f32,
+    pub max_width: f32,
+    /// How far the bed is cut below the ground.
+    pub depth: f32,
+    /// Distance beyond the channel over which the valley rises back to the
+    /// untouched terrain.
+    pub valley_width: f32,
+}
+
+impl Default for RiverSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            resolution: 512,
+            threshold: 400,
+            width: 8.0,
+            max_width: 60.0,
+            depth: 6.0,
+            valley_width: 80.0,
+        }
+    }
+}
+
+/// Lakes filling the closed depressions within `region`, found whenever the
+/// height source is rebuilt, after rivers. Only affects the flat world.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LakeSettings {
+    pub enabled: bool,
+    /// Grown to whole tiles.
+    pub region: Rect,
+    /// Side of the square tiles the region is flooded in.
+    pub tile_size: f32,
+    /// Cells along each side of a tile.
+    pub resolution: u32,
+    /// Depressions shallower than this at their deepest are left dry.
+    pub min_depth: f32,
+}
+
+impl Default for LakeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            region: Rect::new(0.0, 0.0, 4096.0, 4096.0),
+            tile_size: 1024.0,
+            resolution: 128,
+            min_depth: 1.0,
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LODSettings {
+    /// Keep neighbouring leaves within one level of each other (a 2:1 balanced
+    /// quadtree), across the edges between planet faces too.
+    pub balanced: bool,
     pub recheck_interval: f32,
     pub max: f32,
     pub layer_penalty: f32,
     pub min: f32,
+    /// Blend chunk vertices towards their parent's surface as the player moves
+    /// away, so splits and merges don't pop.
+    pub geomorph: bool,
+    /// Fraction of the split distance at which chunks start morphing.
+    pub morph_start: f32,
 }
 
 impl FromWorld for TerrainSettings {
@@ -40,34 +414,41 @@ impl FromWorld for TerrainSettings {
             .get_resource_mut::<Assets<TerrainMaterial>>()
             .expect("TerrainMaterial Assets");
 
-        let mat = materials.add(TerrainMaterial {
-            color: Color::RED,
-            alpha_mode: AlphaMode::Blend,
-        });
+        let mat = materials.add(TerrainMaterial::default());
 
         Self {
             material: mat,
             wireframe: false,
+            mode: TerrainMode::Flat,
             size: Vec2::new(50000.0, 50000.0),
+            planet: PlanetSettings { radius: 8000.0 },
+            seams: SeamSettings::default(),
+            resolution: ResolutionSettings::default(),
 
-            generation: GenerationSettings {
-                seed: 100,
-                amplitude: 0.01,
-                scale: 0.005,
-                octaves: 16,
-                lacunarity: 1.7,
-                persistence: 0.7,
-                frequency: 0.11,
-                exponentiation: 0.81,
-                height: 550.0,
-            },
+            generation: GenerationSettings::default(),
 
             lod: LODSettings {
+                balanced: true,
                 recheck_interval: 0.0,
                 max: 2000.0,
                 layer_penalty: 300.0,
                 min: 56.0,
+                geomorph: true,
+                morph_start: 0.6,
             },
+
+            colliders: ColliderSettings::default(),
+            sculpt: SculptSettings::default(),
+
+            sea_level: DEFAULT_SEA_LEVEL,
+            water: WaterSettings::default(),
+            vegetation: VegetationSettings::default(),
+            props: PropSettings::default(),
+
+            heightmap_export: HeightmapExport::default(),
+            mesh_export: MeshExport::default(),
+            river_export_path: "exports/rivers.ron".into(),
+            save_path: "saves/world.ron".into(),
         }
     }
 }
@@ -75,20 +456,124 @@ impl FromWorld for TerrainSettings {
 #[derive(Resource)]
 pub struct Terrain {
     pub recheck_timer: Timer,
-    pub lod_tree: LODTree,
+    /// One root for the flat world, or one per cube face in planet mode.
+    pub lod_trees: Vec<LODTree>,
+    /// Source sampled by every chunk generation task. Replace it to plug in a
+    /// custom generator; editing the generation parameters in the terrain UI
+    /// rebuilds it from [`GenerationSettings`].
+    pub height_source: Arc<dyn HeightSource>,
+    pub biomes: Arc<BiomeMap>,
+    /// Rivers carved into [`Terrain::height_source`], empty when they are
+    /// disabled.
+    pub rivers: Arc<RiverNetwork>,
+    /// Lakes on [`Terrain::height_source`], empty when they are disabled.
+    pub lakes: Arc<LakeMap>,
+    /// Sculpted offsets on top of the flat world, kept across regeneration.
+    pub sculpt: Arc<SculptLayer>,
+    /// Regions sculpted since the chunks covering them were last rebuilt.
+    pub edited: Vec<Rect>,
+}
+
+impl Terrain {
+    /// Height of the flat world at `x`, `z`, sculpting included.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        self.height_source.height(x as f64, z as f64) as f32 + self.sculpt.height(x, z)
+    }
+
+    /// [`Terrain::height_source`] with [`Terrain::sculpt`] applied, for
+    /// sampling the flat world outside of chunk generation.
+    pub fn sculpted_source(&self) -> Arc<dyn HeightSource> {
+        match self.sculpt.is_empty() {
+            true => self.height_source.clone(),
+            false => Arc::new(SculptedHeight {
+                source: self.height_source.clone(),
+                sculpt: self.sculpt.clone(),
+            }),
+        }
+    }
+
+    /// Water level of the lake at or next to `x`, `z` on the flat world.
+    pub fn lake_level(&self, x: f32, z: f32) -> Option<f32> {
+        self.lakes.level(x, z)
+    }
+
+    /// Biome of the flat world at `x`, `z`, taking the terrain height there
+    /// into account.
+    pub fn biome_at(&self, x: f32, z: f32) -> Biome {
+        let height = self.height_at(x, z) as f64;
+        let climate = self.biomes.climate(x as f64, z as f64, height);
+
+        self.biomes.biome(climate)
+    }
 }
 
 impl FromWorld for Terrain {
     fn from_world(world: &mut World) -> Self {
         let settings = world.get_resource::<TerrainSettings>().unwrap();
-        let lod_tree = LODTree::new(12, Rect::from_corners(Vec2::ZERO, settings.size));
+        let sources = build_terrain_sources(&settings.generation);
 
         Self {
             recheck_timer: Timer::new(
                 Duration::from_secs_f32(settings.lod.recheck_interval),
                 TimerMode::Repeating,
             ),
-            lod_tree,
+            lod_trees: build_lod_trees(settings),
+            height_source: sources.height,
+            biomes: Arc::new(BiomeMap::new(&settings.generation).with_lakes(sources.lakes.clone())),
+            rivers: sources.rivers,
+            lakes: sources.lakes,
+            sculpt: Arc::default(),
+            edited: Vec::new(),
+        }
+    }
+}
+
+/// Rebuild of [`Terrain`]'s sources after the generation settings changed,
+/// run on the async compute pool. The old sources stay in use until it
+/// finishes.
+#[derive(Resource)]
+pub struct SourceRebuild {
+    /// Runs from the last request, so dragging a slider only starts a build
+    /// once it settles.
+    pub debounce: Timer,
+    pub requested: bool,
+    pub task: Option<Task<(TerrainSources, BiomeMap)>>,
+}
+
+impl SourceRebuild {
+    /// Asks for a rebuild from the current settings, dropping one already
+    /// running for older ones.
+    pub fn request(&mut self) {
+        self.requested = true;
+        self.debounce.reset();
+        self.task = None;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.requested || self.task.is_some()
+    }
+}
+
+impl Default for SourceRebuild {
+    fn default() -> Self {
+        Self {
+            debounce: Timer::from_seconds(0.3, TimerMode::Once),
+            requested: false,
+            task: None,
         }
     }
 }
+
+pub fn build_lod_trees(settings: &TerrainSettings) -> Vec<LODTree> {
+    match settings.mode {
+        TerrainMode::Flat => vec![LODTree::new(
+            MAX_LOD_DEPTH,
+            Rect::from_corners(Vec2::ZERO, settings.size),
+            None,
+        )],
+        TerrainMode::Planet => CubeFace::ALL
+            .iter()
+            .map(|face| LODTree::new(MAX_LOD_DEPTH, settings.planet.face_boundary(), Some(*face)))
+            .collect(),
+    }
+}
@@ -0,0 +1,262 @@
+use std::{fmt, fs, path::Path, sync::Arc, time::Duration};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    generation::SculptLayer,
+    planet::PlanetSettings,
+    resources::{
+        GenerationSettings, LODSettings, ResolutionSettings, Terrain, TerrainMode, TerrainSettings,
+        DEFAULT_SEA_LEVEL,
+    },
+    water::WaterSettings,
+};
+
+/// Bumped whenever [`WorldSave`] changes in a way older files can't be read as.
+pub const WORLD_SAVE_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Parse(ron::error::SpannedError),
+    /// The file was written by a newer version than this build knows.
+    Version(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "could not access world file: {err}"),
+            SaveError::Serialize(err) => write!(f, "could not write world: {err}"),
+            SaveError::Parse(err) => write!(f, "could not read world: {err}"),
+            SaveError::Version(version) => write!(
+                f,
+                "world file version {version} is not supported, expected up to {WORLD_SAVE_VERSION}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(err: ron::Error) -> Self {
+        SaveError::Serialize(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SaveError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SaveError::Parse(err)
+    }
+}
+
+/// Everything needed to bring a world back exactly as it was: how it is
+/// generated, the sculpted edits on top and where the spectator was.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorldSave {
+    pub version: u32,
+    pub mode: TerrainMode,
+    pub size: Vec2,
+    pub planet: PlanetSettings,
+    pub resolution: ResolutionSettings,
+    /// Includes the seed.
+    pub generation: GenerationSettings,
+    pub lod: LODSettings,
+    /// Missing from version 1 files, which get the defaults.
+    #[serde(default = "default_sea_level")]
+    pub sea_level: f32,
+    #[serde(default)]
+    pub water: WaterSettings,
+    pub sculpt: SculptLayer,
+    pub spectator_position: Vec3,
+    pub spectator_rotation: Quat,
+}
+
+impl WorldSave {
+    pub fn capture(settings: &TerrainSettings, terrain: &Terrain, spectator: &Transform) -> Self {
+        Self {
+            version: WORLD_SAVE_VERSION,
+            mode: settings.mode,
+            size: settings.size,
+            planet: settings.planet,
+            resolution: settings.resolution.clone(),
+            generation: settings.generation.clone(),
+            lod: settings.lod.clone(),
+            sea_level: settings.sea_level,
+            water: settings.water,
+            sculpt: terrain.sculpt.as_ref().clone(),
+            spectator_position: spectator.translation,
+            spectator_rotation: spectator.rotation,
+        }
+    }
+
+    /// Puts the saved state back. The caller still has to rebuild the height
+    /// source and LOD trees from the restored settings.
+    pub fn restore(
+        self,
+        settings: &mut TerrainSettings,
+        terrain: &mut Terrain,
+        spectator: &mut Transform,
+    ) {
+        settings.mode = self.mode;
+        settings.size = self.size;
+        settings.planet = self.planet;
+        settings.resolution = self.resolution;
+        settings.generation = self.generation;
+        settings.lod = self.lod;
+        settings.sea_level = self.sea_level;
+        settings.water = self.water;
+
+        terrain.sculpt = Arc::new(self.sculpt);
+        terrain.edited.clear();
+        terrain.recheck_timer = Timer::new(
+            Duration::from_secs_f32(settings.lod.recheck_interval),
+            TimerMode::Repeating,
+        );
+
+        spectator.translation = self.spectator_position;
+        spectator.rotation = self.spectator_rotation;
+    }
+}
+
+fn default_sea_level() -> f32 {
+    DEFAULT_SEA_LEVEL
+}
+
+/// Writes `save` as pretty RON, creating parent folders as needed. Arrays are
+/// kept on one line, or sculpt tiles would take a line per sample.
+pub fn save_world(save: &WorldSave, path: &Path) -> Result<(), SaveError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let config = ron::ser::PrettyConfig::default().compact_arrays(true);
+    let contents = ron::ser::to_string_pretty(save, config)?;
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+pub fn load_world(path: &Path) -> Result<WorldSave, SaveError> {
+    let contents = fs::read_to_string(path)?;
+
+    // Check the version on its own first, so newer files fail with a clear
+    // error instead of whatever field they changed.
+    #[derive(Deserialize)]
+    struct Header {
+        version: u32,
+    }
+    let header: Header = ron::from_str(&contents)?;
+
+    // Older files only lack fields that have defaults.
+    if header.version > WORLD_SAVE_VERSION {
+        return Err(SaveError::Version(header.version));
+    }
+
+    Ok(ron::from_str(&contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terrain::{
+        generation::{BiomeMap, SculptBrush},
+        TerrainMaterial,
+    };
+
+    use super::*;
+
+    fn settings() -> TerrainSettings {
+        let mut world = World::new();
+        world.init_resource::<Assets<TerrainMaterial>>();
+
+        let mut settings = TerrainSettings::from_world(&mut world);
+        settings.generation.seed = 4242;
+        settings.resolution.quads = 16;
+        settings.resolution.per_depth = vec![8, 12];
+        settings.lod.max = 3500.0;
+        settings.lod.geomorph = false;
+        settings.sea_level = -12.5;
+        settings.water.rings = 6;
+        settings
+    }
+
+    fn terrain(settings: &TerrainSettings) -> Terrain {
+        let mut sculpt = SculptLayer::default();
+        for center in [Vec2::new(10.0, 20.0), Vec2::new(-75.0, 140.0)] {
+            sculpt.apply(&SculptBrush::default(), center, 0.5, |_, _| 0.0);
+        }
+
+        Terrain {
+            recheck_timer: Timer::default(),
+            lod_trees: Vec::new(),
+            height_source: Arc::new(|_: f64, _: f64| 0.0),
+            biomes: Arc::new(BiomeMap::new(&settings.generation)),
+            rivers: Arc::default(),
+            lakes: Arc::default(),
+            sculpt: Arc::new(sculpt),
+            edited: Vec::new(),
+        }
+    }
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("planetgame-{}-{name}.ron", std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = settings();
+        let terrain = terrain(&settings);
+        let spectator = Transform::from_xyz(1.0, 250.0, -3.0);
+        let save = WorldSave::capture(&settings, &terrain, &spectator);
+
+        let path = path("round-trip");
+        save_world(&save, &path).unwrap();
+        let loaded = load_world(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.version, WORLD_SAVE_VERSION);
+        assert_eq!(loaded.mode, settings.mode);
+        assert_eq!(loaded.size, settings.size);
+        assert_eq!(loaded.planet, settings.planet);
+        assert_eq!(loaded.resolution, settings.resolution);
+        assert_eq!(loaded.generation, settings.generation);
+        assert_eq!(loaded.lod, settings.lod);
+        assert_eq!(loaded.sea_level, settings.sea_level);
+        assert_eq!(loaded.water, settings.water);
+        assert_eq!(loaded.spectator_position, spectator.translation);
+        assert_eq!(loaded.spectator_rotation, spectator.rotation);
+
+        assert!(!loaded.sculpt.is_empty());
+        for x in (-120..60).step_by(7) {
+            for z in (-10..180).step_by(7) {
+                let (x, z) = (x as f32 + 0.3, z as f32 + 0.6);
+                assert_eq!(loaded.sculpt.height(x, z), terrain.sculpt.height(x, z));
+            }
+        }
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let settings = settings();
+        let mut save = WorldSave::capture(&settings, &terrain(&settings), &Transform::IDENTITY);
+        save.version = WORLD_SAVE_VERSION + 1;
+
+        let path = path("newer-version");
+        save_world(&save, &path).unwrap();
+        let loaded = load_world(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(
+            matches!(loaded, Err(SaveError::Version(version)) if version == WORLD_SAVE_VERSION + 1)
+        );
+    }
+}
@@ -1,24 +1,50 @@
+use std::sync::Arc;
+
 use bevy::{
     pbr::wireframe::Wireframe,
     prelude::*,
+    render::{mesh::VertexAttributeValues, primitives::Aabb},
     tasks::{block_on, futures_lite::future, AsyncComputeTaskPool},
+    window::{CursorGrabMode, PrimaryWindow},
 };
+use bevy_egui::EguiContexts;
+use bevy_xpbd_3d::{components::RigidBody, plugins::collision::Collider};
 
 use crate::{
     spectator::components::SpectatorCamera,
     terrain::{
-        generation::ChunkGenerator,
-        lod_tree::{LODLeaf, LODTree},
+        generation::{
+            build_terrain_sources_async, BiomeMap, ChunkGenerator, ChunkShape,
+            ATTRIBUTE_MORPH_NORMAL, ATTRIBUTE_MORPH_POSITION,
+        },
+        lod_tree::{
+            balance, lod_distance_squared, lod_ratio, max_neighbour_depth, LODLeaf, LODTree,
+        },
+        planet::{CubeFace, PlanetSettings},
+        query::TerrainQuery,
         resources::LODSettings,
-        CHUNK_SIZE,
     },
 };
 
 use super::{
-    components::{DeletedTerrainChunk, PendingTerrainChunk, TerrainChunk},
-    resources::{Terrain, TerrainSettings},
+    components::{
+        ChunkCollider, ChunkLod, ChunkMeshReady, ChunkPeak, DeletedTerrainChunk, Geomorph,
+        PendingTerrainChunk, PhysicsActor, TerrainChunk, Underwater,
+    },
+    resources::{
+        build_lod_trees, ColliderSettings, ColliderShape, SourceRebuild, Terrain, TerrainMode,
+        TerrainSettings,
+    },
 };
 
+struct QueuedChunk {
+    entity: Entity,
+    boundary: Rect,
+    parent: Option<Rect>,
+    face: Option<CubeFace>,
+    depth: usize,
+}
+
 pub fn update_lod_tree(
     mut terrain: ResMut<Terrain>,
     mut commands: Commands,
@@ -37,14 +63,26 @@ pub fn update_lod_tree(
 
     fn process(
         tree: &mut LODTree,
-        player: Vec2,
+        player: Vec3,
+        planet: &PlanetSettings,
         settings: &LODSettings,
         commands: &mut Commands,
-        chunk_queue: &mut Vec<(Entity, Rect)>,
+        chunk_queue: &mut Vec<QueuedChunk>,
+        balance: Option<&[LODTree]>,
     ) {
+        let distance = tree.distance_squared(player, planet);
+
         match &tree.leaf {
             LODLeaf::Children(_) => {
-                if !tree.should_collapse(settings, player) {
+                // Merging must not leave a neighbour more than one level deeper.
+                let balanced = || {
+                    balance.is_none_or(|trees| {
+                        max_neighbour_depth(trees, tree.face, tree.boundary)
+                            .is_none_or(|depth| depth <= tree.depth + 1)
+                    })
+                };
+
+                if !tree.should_collapse(settings, distance) && balanced() {
                     let mut cchunks = Vec::new();
                     tree.get_child_chunks_recursive(&mut cchunks);
                     for chunk in cchunks {
@@ -54,103 +92,258 @@ pub fn update_lod_tree(
                 }
             }
             LODLeaf::Chunk(entity) => {
-                if tree.should_collapse(settings, player) && tree.can_collapse() {
-                    commands.entity(entity.clone()).insert(DeletedTerrainChunk);
+                if tree.should_collapse(settings, distance) && tree.can_collapse() {
+                    commands.entity(*entity).insert(DeletedTerrainChunk);
                     assert!(tree.collapse());
                 }
             }
             LODLeaf::Pending => {
-                if tree.should_collapse(settings, player) && tree.can_collapse() {
+                if tree.should_collapse(settings, distance) && tree.can_collapse() {
                     tree.collapse();
                 } else {
                     let entity = commands
                         .spawn((
                             TransformBundle {
-                                local: Transform::from_xyz(
-                                    tree.boundary.min.x,
-                                    0.0,
-                                    tree.boundary.min.y,
-                                ),
+                                local: Transform::from_translation(tree.chunk_origin(planet)),
                                 ..Default::default()
                             },
                             VisibilityBundle::default(),
+                            ChunkLod {
+                                boundary: tree.boundary,
+                                face: tree.face,
+                                depth: tree.depth,
+                            },
                         ))
                         .id();
 
-                    tree.leaf = LODLeaf::Chunk(entity.clone());
-                    chunk_queue.push((entity, tree.boundary));
+                    if let Some(parent) = tree.parent_boundary {
+                        commands.entity(entity).insert(Geomorph {
+                            depth: tree.depth - 1,
+                            parent_size: parent.size().length(),
+                            ..Default::default()
+                        });
+                    }
+
+                    tree.leaf = LODLeaf::Chunk(entity);
+                    chunk_queue.push(QueuedChunk {
+                        entity,
+                        boundary: tree.boundary,
+                        parent: tree.parent_boundary,
+                        face: tree.face,
+                        depth: tree.depth,
+                    });
                 }
             }
         }
 
         if let LODLeaf::Children(children) = &mut tree.leaf {
             for child in children.iter_mut() {
-                process(child, player, settings, commands, chunk_queue);
+                process(
+                    child,
+                    player,
+                    planet,
+                    settings,
+                    commands,
+                    chunk_queue,
+                    balance,
+                );
             }
         }
     }
 
     let mut chunk_queue = Vec::new();
 
-    process(
-        &mut terrain.lod_tree,
-        player.translation.xz(),
-        &settings.lod,
-        &mut commands,
-        &mut chunk_queue,
-    );
+    // Neighbours are looked up across every tree, so a planet's faces stay
+    // balanced along their shared edges too.
+    let snapshot = settings.lod.balanced.then(|| terrain.lod_trees.clone());
+    for tree in terrain.lod_trees.iter_mut() {
+        process(
+            tree,
+            player.translation,
+            &settings.planet,
+            &settings.lod,
+            &mut commands,
+            &mut chunk_queue,
+            snapshot.as_deref(),
+        );
+    }
 
+    if settings.lod.balanced {
+        for chunk in balance(&mut terrain.lod_trees) {
+            commands.entity(chunk).insert(DeletedTerrainChunk);
+        }
+
+        // Give the leaves created by forced splits their chunks right away.
+        let snapshot = terrain.lod_trees.clone();
+        for tree in terrain.lod_trees.iter_mut() {
+            process(
+                tree,
+                player.translation,
+                &settings.planet,
+                &settings.lod,
+                &mut commands,
+                &mut chunk_queue,
+                Some(&snapshot),
+            );
+        }
+    }
+
+    queue_chunk_tasks(chunk_queue, &terrain, &settings, &mut commands);
+}
+
+/// Starts generating each queued chunk's mesh on the async compute pool.
+fn queue_chunk_tasks(
+    chunk_queue: Vec<QueuedChunk>,
+    terrain: &Terrain,
+    settings: &TerrainSettings,
+    commands: &mut Commands,
+) {
     let thread_pool = AsyncComputeTaskPool::get();
 
     for chunk in chunk_queue {
-        let target_chunk_size = chunk.1.size();
+        let resolution = settings.resolution.quads(chunk.depth);
+        let parent_resolution = settings.resolution.quads(chunk.depth.saturating_sub(1));
+
+        let target_chunk_size = chunk.boundary.size();
         let chunk_size = Vec2::new(
-            target_chunk_size.x / (CHUNK_SIZE as f32),
-            target_chunk_size.y / (CHUNK_SIZE as f32),
+            target_chunk_size.x / (resolution as f32),
+            target_chunk_size.y / (resolution as f32),
         );
 
+        let shape = match chunk.face {
+            Some(face) => ChunkShape::Sphere {
+                face,
+                planet: settings.planet,
+            },
+            None => ChunkShape::Flat,
+        };
+
         let task = thread_pool.spawn({
-            let chunk = chunk.clone();
-            let settings = settings.generation.clone();
+            let source = terrain.height_source.clone();
+            let biomes = terrain.biomes.clone();
+            let sculpt = terrain.sculpt.clone();
+            let seams = settings.seams;
 
             async move {
-                let mut generator = ChunkGenerator::new(settings);
-                generator.resolution = 1;
-                generator.position = chunk.1.min;
+                let mut generator = ChunkGenerator::new(source, biomes);
+                generator.resolution = resolution;
+                generator.parent_resolution = parent_resolution;
+                generator.position = chunk.boundary.min;
                 generator.scale = chunk_size;
+                generator.shape = shape;
+                generator.seams = seams;
+                generator.parent = chunk.parent;
+                generator.sculpt = sculpt;
 
                 generator.generate()
             }
         });
 
+        // Planet chunks are generated in world units, flat ones are scaled up.
+        let mesh_scale = match shape {
+            ChunkShape::Sphere { .. } => Vec2::ONE,
+            ChunkShape::Flat => chunk_size,
+        };
+
         commands
-            .entity(chunk.0)
-            .insert(PendingTerrainChunk(task, chunk_size));
+            .entity(chunk.entity)
+            .insert(PendingTerrainChunk(task, mesh_scale));
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn poll_pending_chunks(
     mut commands: Commands,
-    mut tasks: Query<(Entity, &mut PendingTerrainChunk)>,
+    mut tasks: Query<(
+        Entity,
+        &mut PendingTerrainChunk,
+        &Transform,
+        Option<&mut Geomorph>,
+    )>,
+    loaded: Query<(&TerrainChunk, &ChunkCollider, &Children)>,
+    renders: Query<&Handle<Mesh>>,
+    actors: Query<&GlobalTransform, With<PhysicsActor>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut ready: EventWriter<ChunkMeshReady>,
     settings: Res<TerrainSettings>,
 ) {
-    for (entity, mut task) in tasks.iter_mut() {
+    for (entity, mut task, transform, mut geomorph) in tasks.iter_mut() {
         if let Some(mesh) = block_on(future::poll_once(&mut task.0)) {
-            let mesh = meshes.add(mesh);
+            if let Some(geomorph) = geomorph.as_mut() {
+                if let (
+                    Some(VertexAttributeValues::Float32x3(positions)),
+                    Some(VertexAttributeValues::Float32x3(normals)),
+                ) = (
+                    mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+                    mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+                ) {
+                    geomorph.base = positions.clone();
+                    geomorph.base_normals = normals.clone();
+                    geomorph.applied = None;
+                }
+            }
+
+            let mesh_scale = Vec3::new(task.1.x, 1.0, task.1.y);
+            // Given to the render entity up front, as Bevy only computes
+            // bounds once and they have to cover every morph anyway.
+            let mesh_bounds = morph_bounds(&mesh).unwrap_or_default();
+            let bounds = Aabb::from_min_max(
+                transform.translation + Vec3::from(mesh_bounds.min()) * mesh_scale,
+                transform.translation + Vec3::from(mesh_bounds.max()) * mesh_scale,
+            );
+            let peak = ChunkPeak(chunk_peak(&mesh, transform, mesh_scale, &settings));
+
+            // Spawned together with the mesh so the ground never disappears
+            // from under an actor while its chunk is replaced.
+            let collider = wants_collider(&settings.colliders, &bounds, &actors)
+                .then(|| spawn_chunk_collider(&mut commands, entity, &mesh, task.1, &settings))
+                .flatten();
+            if let Some(geomorph) = geomorph.as_mut() {
+                // Built from the unmorphed mesh.
+                geomorph.collider = collider.map(|collider| (collider, Vec2::ZERO));
+            }
 
             commands.entity(entity).remove::<PendingTerrainChunk>();
-            commands
-                .entity(entity)
-                .insert(TerrainChunk(mesh.clone(), task.1));
+            ready.send(ChunkMeshReady { chunk: entity });
+
+            // Rebuilt chunks swap their mesh in place, so they never flicker.
+            if let Ok((chunk, old, children)) = loaded.get(entity) {
+                if let Some(old) = old.collider {
+                    commands.entity(old).despawn_recursive();
+                }
+
+                // Sculpting may have moved the surface past the old bounds.
+                for &child in children {
+                    if renders.get(child) == Ok(&chunk.0) {
+                        commands.entity(child).insert(mesh_bounds);
+                    }
+                }
+
+                meshes.insert(&chunk.0, mesh);
+                commands
+                    .entity(entity)
+                    .insert((ChunkCollider { bounds, collider }, peak));
+                continue;
+            }
+
+            let mesh = meshes.add(mesh);
+
+            commands.entity(entity).insert((
+                TerrainChunk(mesh.clone(), task.1),
+                ChunkCollider { bounds, collider },
+                peak,
+            ));
 
             let child = commands
-                .spawn((MaterialMeshBundle {
-                    mesh,
-                    material: settings.material.clone(),
-                    transform: Transform::from_scale(Vec3::new(task.1.x, 1.0, task.1.y)),
-                    ..Default::default()
-                },))
+                .spawn((
+                    MaterialMeshBundle {
+                        mesh,
+                        material: settings.material.clone(),
+                        transform: Transform::from_scale(mesh_scale),
+                        ..Default::default()
+                    },
+                    mesh_bounds,
+                ))
                 .id();
 
             if settings.wireframe {
@@ -162,11 +355,556 @@ pub fn poll_pending_chunks(
     }
 }
 
+/// Mesh space bounds of a chunk's mesh at any morph, so they hold its own
+/// vertices and its morph targets both.
+fn morph_bounds(mesh: &Mesh) -> Option<Aabb> {
+    let aabb = mesh.compute_aabb()?;
+    let Some(VertexAttributeValues::Float32x3(targets)) = mesh.attribute(ATTRIBUTE_MORPH_POSITION)
+    else {
+        return Some(aabb);
+    };
+
+    let (min, max) = targets.iter().fold(
+        (Vec3::from(aabb.min()), Vec3::from(aabb.max())),
+        |(min, max), &target| (min.min(target.into()), max.max(target.into())),
+    );
+    Some(Aabb::from_min_max(min, max))
+}
+
+fn chunk_peak(
+    mesh: &Mesh,
+    transform: &Transform,
+    mesh_scale: Vec3,
+    settings: &TerrainSettings,
+) -> f32 {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return f32::MIN;
+    };
+
+    positions
+        .iter()
+        .map(|&position| {
+            let position = transform.translation + Vec3::from(position) * mesh_scale;
+            match settings.mode {
+                TerrainMode::Flat => position.y,
+                TerrainMode::Planet => {
+                    position.distance(settings.planet.center()) - settings.planet.radius
+                }
+            }
+        })
+        .fold(f32::MIN, f32::max)
+}
+
+/// Flags the chunks whose peak is below the sea level, so gameplay can tell
+/// where the ground is under water without sampling it.
+pub fn update_underwater_chunks(
+    chunks: Query<(Entity, Ref<ChunkPeak>, Has<Underwater>)>,
+    settings: Res<TerrainSettings>,
+    mut commands: Commands,
+) {
+    for (entity, peak, underwater) in &chunks {
+        if !peak.is_changed() && !settings.is_changed() {
+            continue;
+        }
+
+        let below = peak.0 < settings.sea_level;
+        if below && !underwater {
+            commands.entity(entity).insert(Underwater);
+        } else if !below && underwater {
+            commands.entity(entity).remove::<Underwater>();
+        }
+    }
+}
+
+fn wants_collider(
+    settings: &ColliderSettings,
+    bounds: &Aabb,
+    actors: &Query<&GlobalTransform, With<PhysicsActor>>,
+) -> bool {
+    if !settings.enabled {
+        return false;
+    }
+
+    settings.radius <= 0.0
+        || actors.iter().any(|actor| {
+            let actor = actor.translation_vec3a();
+            let closest = actor.clamp(bounds.min(), bounds.max());
+            actor.distance_squared(closest) <= settings.radius * settings.radius
+        })
+}
+
+/// Builds a static collider matching a chunk's mesh as a child of `chunk`.
+fn spawn_chunk_collider(
+    commands: &mut Commands,
+    chunk: Entity,
+    mesh: &Mesh,
+    mesh_scale: Vec2,
+    settings: &TerrainSettings,
+) -> Option<Entity> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+
+    let scale = Vec3::new(mesh_scale.x, 1.0, mesh_scale.y);
+
+    let (collider, transform) = match (settings.mode, settings.colliders.shape) {
+        (TerrainMode::Flat, ColliderShape::Heightfield) => {
+            // Flat chunks start with their grid vertices, `x` and `z` running
+            // from 0 to the resolution in steps of one.
+            let resolution = positions
+                .iter()
+                .fold(0.0f32, |max, p| max.max(p[0]))
+                .round() as usize;
+            let heights = positions[..(resolution + 1) * (resolution + 1)]
+                .chunks_exact(resolution + 1)
+                .map(|column| column.iter().map(|p| p[1]).collect())
+                .collect();
+
+            // Heightfields are centered on their origin.
+            let size = Vec3::new(resolution as f32, 1.0, resolution as f32) * scale;
+            (
+                Collider::heightfield(heights, size),
+                Transform::from_translation(size * Vec3::new(0.5, 0.0, 0.5)),
+            )
+        }
+        _ => {
+            let vertices = positions.iter().map(|&p| Vec3::from(p) * scale).collect();
+            let indices = match mesh.indices() {
+                Some(indices) => indices.iter().map(|i| i as u32).collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let triangles = indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect();
+
+            (Collider::trimesh(vertices, triangles), Transform::IDENTITY)
+        }
+    };
+
+    let collider = commands
+        .spawn((
+            RigidBody::Static,
+            collider,
+            TransformBundle::from_transform(transform),
+        ))
+        .id();
+    commands.entity(chunk).add_child(collider);
+
+    Some(collider)
+}
+
+/// Gives chunks colliders as actors approach them and removes them once the
+/// actors are a bit further away than they were added at.
+pub fn update_chunk_colliders(
+    mut chunks: Query<(Entity, &TerrainChunk, &mut ChunkCollider), Without<DeletedTerrainChunk>>,
+    actors: Query<&GlobalTransform, With<PhysicsActor>>,
+    meshes: Res<Assets<Mesh>>,
+    settings: Res<TerrainSettings>,
+    mut commands: Commands,
+) {
+    let mut removal = settings.colliders;
+    removal.radius *= 1.25;
+
+    for (entity, chunk, mut collider) in &mut chunks {
+        match collider.collider {
+            None if wants_collider(&settings.colliders, &collider.bounds, &actors) => {
+                let Some(mesh) = meshes.get(&chunk.0) else {
+                    continue;
+                };
+                collider.collider =
+                    spawn_chunk_collider(&mut commands, entity, mesh, chunk.1, &settings);
+            }
+            Some(child) if !wants_collider(&removal, &collider.bounds, &actors) => {
+                commands.entity(child).despawn_recursive();
+                collider.collider = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How far the morph factor may move under a chunk's collider before the
+/// collider is rebuilt to match the mesh again.
+const COLLIDER_MORPH_STEP: f32 = 0.05;
+
+/// Moves every morphing chunk's vertices and normals between its own surface
+/// and its parent's, by how close each vertex is to the distance at which the
+/// parent would replace it. Chunks wholly at a factor they were already at are
+/// left alone, which is most of them as the factor only varies within a band
+/// around the split distance.
+pub fn apply_geomorph(
+    mut chunks: Query<(
+        Entity,
+        &mut Geomorph,
+        &TerrainChunk,
+        &Transform,
+        &mut ChunkCollider,
+    )>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    player: Query<&Transform, With<SpectatorCamera>>,
+    settings: Res<TerrainSettings>,
+    mut last_player: Local<Option<Vec3>>,
+    mut last_lod: Local<Option<LODSettings>>,
+    mut commands: Commands,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+
+    let moved = *last_player != Some(player.translation);
+    *last_player = Some(player.translation);
+
+    // The terrain UI touches the settings every frame, so they are compared
+    // instead of relying on change detection.
+    let retuned = last_lod.as_ref() != Some(&settings.lod);
+    if retuned {
+        *last_lod = Some(settings.lod.clone());
+    }
+
+    let planet = match settings.mode {
+        TerrainMode::Flat => None,
+        TerrainMode::Planet => Some(&settings.planet),
+    };
+    let lod = &settings.lod;
+
+    let factor = |geomorph: &Geomorph, distance_squared: f32| {
+        if !lod.geomorph {
+            return 0.0;
+        }
+
+        let ratio = lod_ratio(lod, geomorph.depth, geomorph.parent_size, distance_squared);
+        let t = ((ratio - lod.morph_start) / (1.0 - lod.morph_start)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+
+    for (entity, mut geomorph, chunk, transform, mut collider) in &mut chunks {
+        if geomorph.base.is_empty() {
+            continue;
+        }
+
+        // The factor only grows with the distance, so the nearest and
+        // farthest points of the chunk bound it.
+        let (near, far) = distance_range(&collider.bounds, player.translation, planet);
+        let range = Vec2::new(factor(&geomorph, near), factor(&geomorph, far));
+        let uniform = range.x == range.y;
+
+        let stale = retuned
+            || geomorph.is_changed()
+            || (moved && !(uniform && geomorph.applied == Some(range)));
+
+        if stale {
+            let Some(mesh) = meshes.get_mut(&chunk.0) else {
+                continue;
+            };
+
+            let (
+                Some(VertexAttributeValues::Float32x3(targets)),
+                Some(VertexAttributeValues::Float32x3(target_normals)),
+            ) = (
+                mesh.attribute(ATTRIBUTE_MORPH_POSITION),
+                mesh.attribute(ATTRIBUTE_MORPH_NORMAL),
+            )
+            else {
+                continue;
+            };
+
+            let mesh_scale = Vec3::new(chunk.1.x, 1.0, chunk.1.y);
+
+            let (positions, normals): (Vec<[f32; 3]>, Vec<[f32; 3]>) = geomorph
+                .base
+                .iter()
+                .zip(&geomorph.base_normals)
+                .zip(targets.iter().zip(target_normals))
+                .map(|((&base, &normal), (&target, &target_normal))| {
+                    let base = Vec3::from(base);
+                    let t = if uniform {
+                        range.x
+                    } else {
+                        let world = transform.translation + base * mesh_scale;
+                        factor(
+                            &geomorph,
+                            lod_distance_squared(world, player.translation, planet),
+                        )
+                    };
+
+                    let normal = Vec3::from(normal).lerp(Vec3::from(target_normal), t);
+                    (
+                        base.lerp(Vec3::from(target), t).to_array(),
+                        normal.normalize_or_zero().to_array(),
+                    )
+                })
+                .unzip();
+
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+            geomorph.bypass_change_detection().applied = Some(range);
+        }
+
+        // Colliders follow the morph too, or actors would stand above or sink
+        // into the ground they see. Ones built elsewhere are rebuilt once, as
+        // what they were built at isn't known.
+        let (Some(current), Some(applied)) = (collider.collider, geomorph.applied) else {
+            continue;
+        };
+        let outdated = geomorph.collider.is_none_or(|(built, at)| {
+            built != current || (at - applied).abs().max_element() > COLLIDER_MORPH_STEP
+        });
+        if !outdated {
+            continue;
+        }
+
+        let Some(mesh) = meshes.get(&chunk.0) else {
+            continue;
+        };
+        commands.entity(current).despawn_recursive();
+        collider.collider = spawn_chunk_collider(&mut commands, entity, mesh, chunk.1, &settings);
+        geomorph.bypass_change_detection().collider =
+            collider.collider.map(|collider| (collider, applied));
+    }
+}
+
+/// [`lod_distance_squared`] from `player` to the nearest and the farthest
+/// point of `bounds`.
+fn distance_range(bounds: &Aabb, player: Vec3, planet: Option<&PlanetSettings>) -> (f32, f32) {
+    let (min, max) = (Vec3::from(bounds.min()), Vec3::from(bounds.max()));
+
+    let Some(planet) = planet else {
+        let player = player.xz();
+        let nearest = player.clamp(min.xz(), max.xz());
+        let farthest = Vec2::select(
+            player.cmplt((min.xz() + max.xz()) * 0.5),
+            max.xz(),
+            min.xz(),
+        );
+        return (
+            nearest.distance_squared(player),
+            farthest.distance_squared(player),
+        );
+    };
+
+    // Within the box, the distance along the surface changes by at most the
+    // straight one scaled down to the surface, with some slack for the arc.
+    let center = Vec3::from(bounds.center);
+    let half = Vec3::from(bounds.half_extents).length();
+    let inner = (center.distance(planet.center()) - half).max(1.0);
+    let spread = half * planet.radius / inner * std::f32::consts::FRAC_PI_2;
+
+    let distance = lod_distance_squared(center, player, Some(planet)).sqrt();
+    (
+        (distance - spread).max(0.0).powi(2),
+        (distance + spread).powi(2),
+    )
+}
+
+/// Paints the sculpt brush onto the flat world where the cursor, or the
+/// center of the screen while the mouse is locked, meets the terrain.
+pub fn sculpt_terrain(
+    mut contexts: EguiContexts,
+    mut terrain: ParamSet<(TerrainQuery, ResMut<Terrain>)>,
+    camera: Query<(&Camera, &GlobalTransform), With<SpectatorCamera>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    settings: Res<TerrainSettings>,
+    time: Res<Time>,
+) {
+    if !settings.sculpt.enabled
+        || settings.mode != TerrainMode::Flat
+        || !mouse.pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+
+    let (Ok((camera, camera_transform)), Ok(window)) = (camera.get_single(), window.get_single())
+    else {
+        return;
+    };
+
+    let ray = match window.cursor.grab_mode {
+        CursorGrabMode::None => window
+            .cursor_position()
+            .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor)),
+        _ => Some(Ray3d::new(
+            camera_transform.translation(),
+            camera_transform.forward(),
+        )),
+    };
+    let Some(hit) = ray.and_then(|ray| terrain.p0().raycast(ray, f32::MAX)) else {
+        return;
+    };
+
+    let mut terrain = terrain.p1();
+    let source = terrain.height_source.clone();
+    let edited = Arc::make_mut(&mut terrain.sculpt).apply(
+        &settings.sculpt.brush,
+        hit.point.xz(),
+        time.delta_seconds(),
+        |x, z| source.height(x as f64, z as f64) as f32,
+    );
+    terrain.edited.push(edited);
+}
+
+/// Regenerates the chunks covering [`Terrain::edited`] regions. Chunks still
+/// generating keep their regions queued, as they may have started before the
+/// edit, and are picked up once they finish.
+pub fn rebuild_edited_chunks(
+    mut terrain: ResMut<Terrain>,
+    pending: Query<(), With<PendingTerrainChunk>>,
+    settings: Res<TerrainSettings>,
+    mut commands: Commands,
+) {
+    if terrain.edited.is_empty() {
+        return;
+    }
+
+    fn collect(
+        tree: &LODTree,
+        region: Rect,
+        settings: &TerrainSettings,
+        out: &mut Vec<QueuedChunk>,
+    ) {
+        // Chunks sample one quad past their edges for their normals.
+        let margin = tree.boundary.width() / settings.resolution.quads(tree.depth) as f32;
+        let bounds =
+            Rect::from_center_half_size(tree.boundary.center(), tree.boundary.half_size() + margin);
+        if bounds.intersect(region).is_empty() {
+            return;
+        }
+
+        match &tree.leaf {
+            LODLeaf::Children(children) => {
+                for child in children.iter() {
+                    collect(child, region, settings, out);
+                }
+            }
+            LODLeaf::Chunk(entity) => out.push(QueuedChunk {
+                entity: *entity,
+                boundary: tree.boundary,
+                parent: tree.parent_boundary,
+                face: tree.face,
+                depth: tree.depth,
+            }),
+            LODLeaf::Pending => {}
+        }
+    }
+
+    let mut chunk_queue: Vec<QueuedChunk> = Vec::new();
+    let mut waiting = Vec::new();
+
+    for region in std::mem::take(&mut terrain.edited) {
+        let mut chunks = Vec::new();
+        for tree in &terrain.lod_trees {
+            collect(tree, region, &settings, &mut chunks);
+        }
+
+        if chunks.iter().any(|chunk| pending.contains(chunk.entity)) {
+            waiting.push(region);
+            continue;
+        }
+
+        for chunk in chunks {
+            if !chunk_queue
+                .iter()
+                .any(|queued| queued.entity == chunk.entity)
+            {
+                chunk_queue.push(chunk);
+            }
+        }
+    }
+
+    terrain.edited = waiting;
+    queue_chunk_tasks(chunk_queue, &terrain, &settings, &mut commands);
+}
+
+/// Starts rebuilding the terrain sources once [`SourceRebuild`] requests
+/// have settled, and swaps them in when the build finishes, regenerating
+/// every chunk.
+pub fn rebuild_terrain_sources(
+    mut rebuild: ResMut<SourceRebuild>,
+    mut terrain: ResMut<Terrain>,
+    settings: Res<TerrainSettings>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    if rebuild.requested {
+        rebuild.debounce.tick(time.delta());
+
+        if rebuild.debounce.finished() {
+            let generation = settings.generation.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let sources = build_terrain_sources_async(&generation).await;
+                let biomes = BiomeMap::new(&generation).with_lakes(sources.lakes.clone());
+                (sources, biomes)
+            });
+
+            rebuild.requested = false;
+            rebuild.task = Some(task);
+        }
+    }
+
+    let Some(task) = rebuild.task.as_mut() else {
+        return;
+    };
+    let Some((sources, biomes)) = block_on(future::poll_once(task)) else {
+        return;
+    };
+    rebuild.task = None;
+
+    terrain.height_source = sources.height;
+    terrain.rivers = sources.rivers;
+    terrain.lakes = sources.lakes;
+    terrain.biomes = Arc::new(biomes);
+
+    regenerate_chunks(&mut terrain, &settings, &mut commands);
+}
+
+/// Marks every chunk for deletion and starts the LOD trees over, so all of
+/// them are generated again.
+pub fn regenerate_chunks(
+    terrain: &mut Terrain,
+    settings: &TerrainSettings,
+    commands: &mut Commands,
+) {
+    let mut chunks = Vec::new();
+    for tree in terrain.lod_trees.iter() {
+        tree.get_child_chunks_recursive(&mut chunks);
+    }
+    for chunk in chunks {
+        commands.entity(chunk).insert(DeletedTerrainChunk);
+    }
+
+    terrain.lod_trees = build_lod_trees(settings);
+}
+
 pub fn process_marked_for_deletion(
-    chunks: Query<Entity, (With<DeletedTerrainChunk>, Without<PendingTerrainChunk>)>,
+    chunks: Query<(Entity, &ChunkLod, Has<PendingTerrainChunk>), With<DeletedTerrainChunk>>,
+    colliders: Query<&ChunkCollider>,
+    pending: Query<&ChunkLod, (With<PendingTerrainChunk>, Without<DeletedTerrainChunk>)>,
     mut commands: Commands,
 ) {
-    for entity in &chunks {
+    for (entity, lod, generating) in &chunks {
+        if generating {
+            continue;
+        }
+
+        // Keep colliders around, hidden, until the chunks replacing them
+        // exist, so nothing falls through where they were.
+        let has_collider = colliders
+            .get(entity)
+            .is_ok_and(|collider| collider.collider.is_some());
+        let replaced_later = pending.iter().any(|other| {
+            other.face == lod.face && !other.boundary.intersect(lod.boundary).is_empty()
+        });
+        if has_collider && replaced_later {
+            commands.entity(entity).insert(Visibility::Hidden);
+            continue;
+        }
+
         commands.entity(entity).despawn_recursive();
     }
 }
@@ -0,0 +1,291 @@
+use std::sync::Arc;
+
+use bevy::{
+    core::cast_slice,
+    core_pipeline::core_3d::Transparent3d,
+    ecs::{
+        query::QueryItem,
+        system::{lifetimeless::SRes, SystemParamItem},
+    },
+    pbr::{
+        MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup,
+    },
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult,
+            RenderPhase, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            Buffer, BufferInitDescriptor, BufferUsages, PipelineCache, RenderPipelineDescriptor,
+            SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines,
+            VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
+        },
+        renderer::RenderDevice,
+        view::ExtractedView,
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+/// One placed instance of a vegetation mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceData {
+    /// World position of the mesh's origin.
+    pub position: Vec3,
+    pub scale: f32,
+    pub rotation: Quat,
+    pub color: Vec3,
+}
+
+impl InstanceData {
+    /// How the instance is laid out in the instance buffer, matching the
+    /// `i_*` attributes of the vegetation shader.
+    fn packed(&self) -> [Vec4; 3] {
+        [
+            self.position.extend(self.scale),
+            Vec4::from(self.rotation),
+            self.color.extend(1.0),
+        ]
+    }
+}
+
+/// Instances drawn with the entity's mesh in a single draw call. They are in
+/// world space, the entity's transform is only used to sort it.
+#[derive(Component, Clone)]
+pub struct VegetationInstances(pub Arc<[InstanceData]>);
+
+impl ExtractComponent for VegetationInstances {
+    type QueryData = &'static VegetationInstances;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+pub struct InstancingPlugin;
+
+impl Plugin for InstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractComponentPlugin::<VegetationInstances>::extract_visible());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_command::<Transparent3d, DrawVegetation>()
+            .init_resource::<SpecializedMeshPipelines<VegetationPipeline>>()
+            .init_resource::<InstanceBuffers>()
+            .add_systems(
+                Render,
+                (
+                    queue_vegetation.in_set(RenderSet::QueueMeshes),
+                    prepare_instance_buffers.in_set(RenderSet::PrepareResources),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<VegetationPipeline>();
+        }
+    }
+}
+
+/// Uploaded instance buffers by entity, kept until the entity stops being
+/// drawn so instances are only uploaded once.
+#[derive(Resource, Default)]
+struct InstanceBuffers(HashMap<Entity, (Arc<[InstanceData]>, Buffer)>);
+
+fn prepare_instance_buffers(
+    instances: Query<(Entity, &VegetationInstances)>,
+    mut buffers: ResMut<InstanceBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    let mut drawn = HashMap::with_capacity(buffers.0.len());
+
+    for (entity, instances) in &instances {
+        let cached = buffers
+            .0
+            .remove(&entity)
+            .filter(|(cached, _)| Arc::ptr_eq(cached, &instances.0));
+
+        let entry = cached.unwrap_or_else(|| {
+            let data: Vec<Vec4> = instances.0.iter().flat_map(InstanceData::packed).collect();
+            let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("vegetation instance buffer"),
+                contents: cast_slice(&data),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            });
+
+            (instances.0.clone(), buffer)
+        });
+
+        drawn.insert(entity, entry);
+    }
+
+    buffers.0 = drawn;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_vegetation(
+    transparent_3d_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    pipeline: Res<VegetationPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<VegetationPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    vegetation: Query<Entity, With<VegetationInstances>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    let draw_vegetation = transparent_3d_draw_functions.read().id::<DrawVegetation>();
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+    for (view, mut transparent_phase) in &mut views {
+        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+        let rangefinder = view.rangefinder3d();
+
+        for entity in &vegetation {
+            let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let Ok(pipeline) = pipelines.specialize(&pipeline_cache, &pipeline, key, &mesh.layout)
+            else {
+                continue;
+            };
+
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function: draw_vegetation,
+                distance: rangefinder
+                    .distance_translation(&mesh_instance.transforms.transform.translation),
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+/// The mesh pipeline with the vegetation shader and an instance buffer next
+/// to the mesh's vertices.
+#[derive(Resource)]
+struct VegetationPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for VegetationPipeline {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            shader: world
+                .resource::<AssetServer>()
+                .load("shaders/vegetation/instanced.wgsl"),
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for VegetationPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+
+        descriptor.vertex.shader = self.shader.clone();
+        // Past the locations the mesh pipeline uses for mesh attributes.
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: VertexFormat::Float32x4.size() * 3,
+            step_mode: VertexStepMode::Instance,
+            attributes: (0..3)
+                .map(|index| VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size() * index,
+                    shader_location: 8 + index as u32,
+                })
+                .collect(),
+        });
+
+        // Grass blades are single sided.
+        descriptor.primitive.cull_mode = None;
+
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = self.shader.clone();
+        }
+
+        Ok(descriptor)
+    }
+}
+
+type DrawVegetation = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawMeshInstanced,
+);
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (
+        SRes<RenderAssets<Mesh>>,
+        SRes<RenderMeshInstances>,
+        SRes<InstanceBuffers>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        _entity: Option<()>,
+        (meshes, render_mesh_instances, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some((instances, buffer)) = buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        let count = instances.len() as u32;
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, buffer.slice(..));
+
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count: indices,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*indices, 0, 0..count);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, 0..count);
+            }
+        }
+
+        RenderCommandResult::Success
+    }
+}
@@ -0,0 +1,158 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::PrimitiveTopology, render_asset::RenderAssetUsages},
+};
+
+use super::VegetationKind;
+
+/// The low poly mesh every instance of a [`VegetationKind`] repeats.
+#[derive(Resource)]
+pub struct VegetationMeshes {
+    tree: Handle<Mesh>,
+    bush: Handle<Mesh>,
+    grass: Handle<Mesh>,
+}
+
+impl VegetationMeshes {
+    pub fn get(&self, kind: VegetationKind) -> Handle<Mesh> {
+        match kind {
+            VegetationKind::Tree => self.tree.clone(),
+            VegetationKind::Bush => self.bush.clone(),
+            VegetationKind::Grass => self.grass.clone(),
+        }
+    }
+}
+
+impl FromWorld for VegetationMeshes {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+
+        Self {
+            tree: meshes.add(tree_mesh()),
+            bush: meshes.add(bush_mesh()),
+            grass: meshes.add(grass_mesh()),
+        }
+    }
+}
+
+const TRUNK: [f32; 4] = [0.35, 0.25, 0.15, 0.0];
+/// Alpha is how much of the instance's colour a vertex takes, so white
+/// foliage ends up exactly the instance's colour.
+const FOLIAGE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Flat shaded triangles with a colour each.
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+}
+
+impl MeshBuilder {
+    /// Adds a triangle facing away from `inside`.
+    fn triangle(&mut self, corners: [Vec3; 3], inside: Vec3, color: [f32; 4]) {
+        let [a, mut b, mut c] = corners;
+        let mut normal = (b - a).cross(c - a).normalize_or_zero();
+        if normal.dot((a + b + c) / 3.0 - inside) < 0.0 {
+            std::mem::swap(&mut b, &mut c);
+            normal = -normal;
+        }
+
+        for corner in [a, b, c] {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.colors.push(color);
+        }
+    }
+
+    /// A cone around the `y` axis from a ring at `base` to a tip at `apex`,
+    /// without a cap.
+    fn cone(&mut self, base: f32, radius: f32, apex: f32, sides: usize, color: [f32; 4]) {
+        let inside = Vec3::Y * (base + apex) * 0.5;
+        for side in 0..sides {
+            let [a, b] = [side, side + 1].map(|i| ring(i, sides, radius, base));
+            self.triangle([a, b, Vec3::Y * apex], inside, color);
+        }
+    }
+
+    fn cap(&mut self, height: f32, radius: f32, sides: usize, inside: Vec3, color: [f32; 4]) {
+        for side in 0..sides {
+            let [a, b] = [side, side + 1].map(|i| ring(i, sides, radius, height));
+            self.triangle([Vec3::Y * height, a, b], inside, color);
+        }
+    }
+
+    /// An open cylinder around the `y` axis.
+    fn prism(&mut self, bottom: f32, top: f32, radius: f32, sides: usize, color: [f32; 4]) {
+        let inside = Vec3::Y * (bottom + top) * 0.5;
+        for side in 0..sides {
+            let [a, b] = [side, side + 1].map(|i| ring(i, sides, radius, bottom));
+            let [c, d] = [side, side + 1].map(|i| ring(i, sides, radius, top));
+            self.triangle([a, c, b], inside, color);
+            self.triangle([b, c, d], inside, color);
+        }
+    }
+
+    fn build(self) -> Mesh {
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, self.colors)
+    }
+}
+
+fn ring(index: usize, sides: usize, radius: f32, height: f32) -> Vec3 {
+    let angle = index as f32 / sides as f32 * TAU;
+    Vec3::new(angle.cos() * radius, height, angle.sin() * radius)
+}
+
+/// A conifer about eight units tall: a trunk under two stacked cones.
+fn tree_mesh() -> Mesh {
+    let mut builder = MeshBuilder::default();
+
+    builder.prism(-0.5, 2.0, 0.25, 5, TRUNK);
+    for (base, radius, apex) in [(1.5, 2.0, 5.5), (3.5, 1.5, 8.0)] {
+        builder.cone(base, radius, apex, 7, FOLIAGE);
+        builder.cap(base, radius, 7, Vec3::Y * apex, FOLIAGE);
+    }
+
+    builder.build()
+}
+
+/// A rounded clump about one and a half units tall.
+fn bush_mesh() -> Mesh {
+    let mut builder = MeshBuilder::default();
+
+    builder.cone(0.6, 1.0, -0.2, 7, FOLIAGE);
+    builder.cone(0.6, 1.0, 1.5, 7, FOLIAGE);
+
+    builder.build()
+}
+
+/// A tuft of three crossed blades. Blades are lit like the ground under them
+/// rather than by their own facing, or half of them would always be dark.
+fn grass_mesh() -> Mesh {
+    let mut builder = MeshBuilder::default();
+
+    for blade in 0..3 {
+        let angle = blade as f32 / 3.0 * TAU * 0.5;
+        let across = Vec3::new(angle.cos(), 0.0, angle.sin()) * 0.08;
+        let lean = Vec3::new(-angle.sin(), 0.0, angle.cos()) * 0.15;
+
+        for corner in [-across, across, Vec3::Y * 0.6 + lean] {
+            builder.positions.push(corner.to_array());
+            builder.normals.push([0.0, 1.0, 0.0]);
+        }
+        // Darker at the roots.
+        builder
+            .colors
+            .extend([[0.6, 0.6, 0.6, 1.0], [0.6, 0.6, 0.6, 1.0], FOLIAGE]);
+    }
+
+    builder.build()
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    render::view::NoFrustumCulling,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use strum::{Display, EnumCount, EnumIter};
+
+use super::{
+    components::{ChunkLod, ChunkMeshReady, DeletedTerrainChunk, TerrainChunk},
+    generation::BiomeWeights,
+    resources::{Terrain, TerrainMode, TerrainSettings},
+    systems::poll_pending_chunks,
+};
+
+pub use self::{instancing::*, scatter::*};
+
+mod instancing;
+mod meshes;
+mod scatter;
+
+pub struct VegetationPlugin;
+
+impl Plugin for VegetationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(InstancingPlugin);
+        app.init_resource::<meshes::VegetationMeshes>();
+
+        app.add_systems(
+            Update,
+            (scatter_vegetation, poll_vegetation)
+                .chain()
+                .after(poll_pending_chunks),
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumCount, EnumIter)]
+pub enum VegetationKind {
+    Tree,
+    Bush,
+    Grass,
+}
+
+/// Where and how densely one [`VegetationKind`] grows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScatterRule {
+    pub kind: VegetationKind,
+    /// Smallest distance between two instances on chunks at `full_depth` or
+    /// deeper.
+    pub spacing: f32,
+    /// Shallowest LOD depth the kind is placed on, so it is only drawn as far
+    /// out as chunks this deep reach.
+    pub min_depth: usize,
+    /// Depth from which the kind grows at its full density. Every level above
+    /// it multiplies the spacing by `thinning`.
+    pub full_depth: usize,
+    pub thinning: f32,
+    /// Height range above the sea level the kind grows in.
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Steepest ground it grows on, as one minus the cosine of the slope like
+    /// the terrain material's rock threshold.
+    pub max_slope: f32,
+    /// Chance for a sampled point to be kept in each biome.
+    pub density: BiomeWeights,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Tint of the instances' foliage.
+    pub color: Vec3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VegetationSettings {
+    pub enabled: bool,
+    /// Most instances of one kind on a single chunk.
+    pub max_instances: usize,
+    pub rules: Vec<ScatterRule>,
+}
+
+impl Default for VegetationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_instances: 20000,
+            // Biomes in declaration order: snow, tundra, taiga, grassland,
+            // forest, desert, savanna and rainforest.
+            rules: vec![
+                ScatterRule {
+                    kind: VegetationKind::Tree,
+                    spacing: 7.0,
+                    min_depth: 7,
+                    full_depth: 10,
+                    thinning: 1.5,
+                    min_height: 1.5,
+                    max_height: 600.0,
+                    max_slope: 0.2,
+                    density: [0.0, 0.05, 0.7, 0.08, 0.9, 0.0, 0.12, 1.0],
+                    min_scale: 0.7,
+                    max_scale: 1.4,
+                    color: Vec3::new(0.22, 0.42, 0.18),
+                },
+                ScatterRule {
+                    kind: VegetationKind::Bush,
+                    spacing: 5.0,
+                    min_depth: 9,
+                    full_depth: 11,
+                    thinning: 1.5,
+                    min_height: 1.0,
+                    max_height: 700.0,
+                    max_slope: 0.3,
+                    density: [0.0, 0.3, 0.3, 0.25, 0.5, 0.08, 0.45, 0.8],
+                    min_scale: 0.6,
+                    max_scale: 1.3,
+                    color: Vec3::new(0.3, 0.48, 0.2),
+                },
+                ScatterRule {
+                    kind: VegetationKind::Grass,
+                    spacing: 1.2,
+                    min_depth: 11,
+                    full_depth: 12,
+                    thinning: 1.5,
+                    min_height: 0.5,
+                    max_height: 500.0,
+                    max_slope: 0.25,
+                    density: [0.0, 0.4, 0.5, 1.0, 0.7, 0.05, 0.8, 0.9],
+                    min_scale: 0.6,
+                    max_scale: 1.3,
+                    color: Vec3::new(0.38, 0.58, 0.22),
+                },
+            ],
+        }
+    }
+}
+
+/// Scatter running for a chunk whose mesh was just generated.
+#[derive(Component)]
+pub struct PendingVegetation(Task<Vec<(VegetationKind, Vec<InstanceData>)>>);
+
+/// Instanced vegetation entities parented to a chunk, one per kind.
+#[derive(Component)]
+pub struct ChunkVegetation(pub Vec<Entity>);
+
+/// Starts scattering vegetation over every chunk whose mesh is ready, or over
+/// all loaded chunks when the vegetation settings changed.
+#[allow(clippy::too_many_arguments)]
+fn scatter_vegetation(
+    mut commands: Commands,
+    mut ready: EventReader<ChunkMeshReady>,
+    chunks: Query<
+        (
+            &ChunkLod,
+            &TerrainChunk,
+            &Transform,
+            Option<&ChunkVegetation>,
+        ),
+        Without<DeletedTerrainChunk>,
+    >,
+    all: Query<Entity, (With<TerrainChunk>, Without<DeletedTerrainChunk>)>,
+    meshes: Res<Assets<Mesh>>,
+    terrain: Res<Terrain>,
+    settings: Res<TerrainSettings>,
+    mut applied: Local<Option<VegetationSettings>>,
+) {
+    let mut targets: Vec<Entity> = ready.read().map(|event| event.chunk).collect();
+
+    if applied.as_ref() != Some(&settings.vegetation) {
+        *applied = Some(settings.vegetation.clone());
+        targets = all.iter().collect();
+    }
+
+    let thread_pool = AsyncComputeTaskPool::get();
+    let center = match settings.mode {
+        TerrainMode::Flat => None,
+        TerrainMode::Planet => Some(settings.planet.center()),
+    };
+
+    for entity in targets {
+        let Ok((lod, chunk, transform, vegetation)) = chunks.get(entity) else {
+            continue;
+        };
+
+        if let Some(vegetation) = vegetation {
+            for &child in &vegetation.0 {
+                commands.entity(child).despawn_recursive();
+            }
+            commands.entity(entity).remove::<ChunkVegetation>();
+        }
+
+        // Dropping a running task cancels it.
+        commands.entity(entity).remove::<PendingVegetation>();
+
+        let vegetation = &settings.vegetation;
+        if !vegetation.enabled
+            || vegetation
+                .rules
+                .iter()
+                .all(|rule| lod.depth < rule.min_depth)
+        {
+            continue;
+        }
+
+        let Some(mesh) = meshes.get(&chunk.0) else {
+            continue;
+        };
+
+        let Some(grid) = ChunkGrid::from_mesh(
+            mesh,
+            settings.resolution.quads(lod.depth) as usize,
+            transform.translation,
+            Vec3::new(chunk.1.x, 1.0, chunk.1.y),
+        ) else {
+            continue;
+        };
+
+        let ground = ScatterGround {
+            grid,
+            center,
+            radius: settings.planet.radius,
+            sea_level: settings.sea_level,
+            // Lakes are only filled on the flat world.
+            lakes: center.is_none().then(|| terrain.lakes.clone()),
+        };
+
+        let seed = chunk_seed(settings.generation.seed, lod);
+        let depth = lod.depth;
+        let vegetation = vegetation.clone();
+
+        let task = thread_pool.spawn(async move {
+            vegetation
+                .rules
+                .iter()
+                .filter(|rule| depth >= rule.min_depth)
+                .map(|rule| {
+                    let instances = ground.scatter(rule, depth, seed, vegetation.max_instances);
+                    (rule.kind, instances)
+                })
+                .collect()
+        });
+
+        commands.entity(entity).insert(PendingVegetation(task));
+    }
+}
+
+/// Spawns the instanced entities of every finished scatter as children of
+/// its chunk.
+fn poll_vegetation(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut PendingVegetation), Without<DeletedTerrainChunk>>,
+    meshes: Res<meshes::VegetationMeshes>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(groups) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+
+        let children: Vec<Entity> = groups
+            .into_iter()
+            .filter(|(_, instances)| !instances.is_empty())
+            .map(|(kind, instances)| {
+                commands
+                    .spawn((
+                        meshes.get(kind),
+                        SpatialBundle::INHERITED_IDENTITY,
+                        VegetationInstances(Arc::from(instances)),
+                        // Instances spread over the whole chunk, far outside
+                        // the bounds of the mesh they repeat.
+                        NoFrustumCulling,
+                    ))
+                    .id()
+            })
+            .collect();
+
+        commands
+            .entity(entity)
+            .remove::<PendingVegetation>()
+            .push_children(&children)
+            .insert(ChunkVegetation(children));
+    }
+}
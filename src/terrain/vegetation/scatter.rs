@@ -0,0 +1,338 @@
+use std::{
+    f32::consts::{SQRT_2, TAU},
+    sync::Arc,
+};
+
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::terrain::{
+    components::ChunkLod,
+    generation::{BiomeWeights, LakeMap, ATTRIBUTE_BIOME_WEIGHTS_0, ATTRIBUTE_BIOME_WEIGHTS_1},
+};
+
+use super::{InstanceData, ScatterRule};
+
+/// Grid vertices of a chunk's mesh in world space.
+pub struct ChunkGrid {
+    /// Quads along each side.
+    pub resolution: usize,
+    /// `(resolution + 1)²` positions, one column of constant `x` after the
+    /// other like the mesh lays them out.
+    pub positions: Vec<Vec3>,
+    pub biomes: Vec<BiomeWeights>,
+}
+
+impl ChunkGrid {
+    /// Reads the grid of a generated chunk mesh placed at `origin` and scaled
+    /// by `scale`, leaving out its skirts.
+    pub fn from_mesh(mesh: &Mesh, resolution: usize, origin: Vec3, scale: Vec3) -> Option<Self> {
+        let count = (resolution + 1) * (resolution + 1);
+
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        let (
+            Some(VertexAttributeValues::Float32x4(first)),
+            Some(VertexAttributeValues::Float32x4(last)),
+        ) = (
+            mesh.attribute(ATTRIBUTE_BIOME_WEIGHTS_0),
+            mesh.attribute(ATTRIBUTE_BIOME_WEIGHTS_1),
+        )
+        else {
+            return None;
+        };
+
+        if positions.len() < count || first.len() < count || last.len() < count {
+            return None;
+        }
+
+        Some(Self {
+            resolution,
+            positions: positions[..count]
+                .iter()
+                .map(|&position| origin + Vec3::from(position) * scale)
+                .collect(),
+            biomes: first
+                .iter()
+                .zip(last)
+                .take(count)
+                .map(|(a, b)| [a[0], a[1], a[2], a[3], b[0], b[1], b[2], b[3]])
+                .collect(),
+        })
+    }
+
+    fn index(&self, i: usize, j: usize) -> usize {
+        i * (self.resolution + 1) + j
+    }
+
+    /// World length of the grid along each of its two axes.
+    fn size(&self) -> Vec2 {
+        let corner = self.positions[0];
+        let last = self.resolution;
+        Vec2::new(
+            self.positions[self.index(last, 0)].distance(corner),
+            self.positions[self.index(0, last)].distance(corner),
+        )
+    }
+}
+
+/// What the ground under a chunk looks like to the rules deciding what grows
+/// on it.
+pub struct ScatterGround {
+    pub grid: ChunkGrid,
+    /// Centre of the planet in planet mode.
+    pub center: Option<Vec3>,
+    pub radius: f32,
+    pub sea_level: f32,
+    pub lakes: Option<Arc<LakeMap>>,
+}
+
+struct GroundPoint {
+    position: Vec3,
+    normal: Vec3,
+    up: Vec3,
+    height: f32,
+    biomes: BiomeWeights,
+}
+
+impl ScatterGround {
+    /// Interpolates the grid at `uv`, both running from 0 to 1 across it.
+    fn point(&self, uv: Vec2) -> GroundPoint {
+        let grid = &self.grid;
+        let last = grid.resolution as f32;
+        let cell = (uv * last).clamp(Vec2::ZERO, Vec2::splat(last - 1e-3));
+        let (i, j) = (cell.x as usize, cell.y as usize);
+        let t = cell - Vec2::new(i as f32, j as f32);
+
+        let corners = [
+            grid.index(i, j),
+            grid.index(i + 1, j),
+            grid.index(i, j + 1),
+            grid.index(i + 1, j + 1),
+        ];
+        let weights = [
+            (1.0 - t.x) * (1.0 - t.y),
+            t.x * (1.0 - t.y),
+            (1.0 - t.x) * t.y,
+            t.x * t.y,
+        ];
+
+        let position: Vec3 = corners
+            .iter()
+            .zip(weights)
+            .map(|(&corner, weight)| grid.positions[corner] * weight)
+            .sum();
+
+        let mut biomes = BiomeWeights::default();
+        for (&corner, weight) in corners.iter().zip(weights) {
+            for (biome, value) in biomes.iter_mut().zip(grid.biomes[corner]) {
+                *biome += value * weight;
+            }
+        }
+
+        let [a, b, c, d] = corners.map(|corner| grid.positions[corner]);
+        let (along_x, along_z) = ((b - a) + (d - c), (c - a) + (d - b));
+
+        let (up, height) = match self.center {
+            Some(center) => (
+                (position - center).normalize_or_zero(),
+                position.distance(center) - self.radius,
+            ),
+            None => (Vec3::Y, position.y),
+        };
+
+        // Face windings differ between cube faces, so just point it outwards.
+        let normal = along_z.cross(along_x).normalize_or_zero();
+        let normal = if normal.dot(up) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        GroundPoint {
+            position,
+            normal,
+            up,
+            height,
+            biomes,
+        }
+    }
+
+    /// Places the instances of `rule` over the chunk. `seed` should come from
+    /// [`chunk_seed`], so a chunk always gets the same vegetation.
+    pub fn scatter(
+        &self,
+        rule: &ScatterRule,
+        depth: usize,
+        seed: u64,
+        max_instances: usize,
+    ) -> Vec<InstanceData> {
+        let mut rng = StdRng::seed_from_u64(mix(seed ^ rule.kind as u64));
+
+        // Coarser chunks cover more ground, so they get sparser vegetation.
+        // The spacing is also kept from getting so small next to the chunk
+        // that the sampling grid would get huge.
+        let size = self.grid.size();
+        let levels = rule.full_depth.saturating_sub(depth) as i32;
+        let spacing =
+            (rule.spacing * rule.thinning.max(1.0).powi(levels)).max(size.max_element() / 1024.0);
+
+        let points = poisson_disk(size, spacing, &mut rng, max_instances);
+
+        points
+            .into_iter()
+            .filter_map(|sample| {
+                // Drawn up front so one rejected point doesn't shift the
+                // values every following point gets.
+                let (chance, scale, yaw, shade): (f32, f32, f32, f32) = rng.gen();
+
+                let point = self.point(sample / size);
+                let height = point.height - self.sea_level;
+                let slope = 1.0 - point.normal.dot(point.up);
+
+                if height < rule.min_height || height > rule.max_height || slope > rule.max_slope {
+                    return None;
+                }
+
+                let flooded = self.lakes.as_ref().is_some_and(|lakes| {
+                    lakes
+                        .level(point.position.x, point.position.z)
+                        .is_some_and(|level| level > point.position.y)
+                });
+                if flooded {
+                    return None;
+                }
+
+                // Thins out towards the steepest slope instead of stopping
+                // at it.
+                let density: f32 = point
+                    .biomes
+                    .iter()
+                    .zip(rule.density)
+                    .map(|(weight, density)| weight * density)
+                    .sum::<f32>()
+                    * (1.0 - slope / rule.max_slope.max(f32::EPSILON));
+
+                if chance >= density {
+                    return None;
+                }
+
+                let scale = rule.min_scale + (rule.max_scale - rule.min_scale) * scale;
+                let rotation =
+                    Quat::from_rotation_arc(Vec3::Y, point.up) * Quat::from_rotation_y(yaw * TAU);
+
+                // Sunk in a little, so the downhill side doesn't float on slopes.
+                let position = point.position - point.up * (0.1 + slope * 4.0) * scale;
+
+                Some(InstanceData {
+                    position,
+                    scale,
+                    rotation,
+                    color: rule.color * (0.85 + shade * 0.3),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Seed for the vegetation of the chunk at `lod`, the same every time the
+/// chunk is loaded for a world seed.
+pub fn chunk_seed(seed: u32, lod: &ChunkLod) -> u64 {
+    let face = lod.face.map_or(0, |face| face as u64 + 1);
+    hash_seed([
+        seed as u64,
+        face,
+        lod.depth as u64,
+        lod.boundary.min.x.to_bits() as u64,
+        lod.boundary.min.y.to_bits() as u64,
+    ])
+}
+
+/// Combines `values` into one well mixed seed.
+pub fn hash_seed(values: impl IntoIterator<Item = u64>) -> u64 {
+    values.into_iter().fold(0, |hash, value| mix(hash ^ value))
+}
+
+/// SplitMix64 finaliser, spreads every input bit over the whole output.
+fn mix(value: u64) -> u64 {
+    let mut value = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// Bridson's Poisson-disk sampling over `[0, size]`: points at least `radius`
+/// apart that still cover the whole area evenly.
+pub fn poisson_disk(size: Vec2, radius: f32, rng: &mut impl Rng, max_points: usize) -> Vec<Vec2> {
+    const ATTEMPTS: usize = 30;
+
+    if radius <= 0.0 || size.min_element() <= 0.0 || max_points == 0 {
+        return Vec::new();
+    }
+
+    // Cells small enough to hold at most one point each.
+    let cell = radius / SQRT_2;
+    let columns = ((size.x / cell).ceil() as usize).max(1);
+    let rows = ((size.y / cell).ceil() as usize).max(1);
+    let cell_of = |point: Vec2| {
+        (
+            ((point.x / cell) as usize).min(columns - 1),
+            ((point.y / cell) as usize).min(rows - 1),
+        )
+    };
+
+    let mut grid = vec![None::<usize>; columns * rows];
+    let mut points = vec![Vec2::new(
+        rng.gen::<f32>() * size.x,
+        rng.gen::<f32>() * size.y,
+    )];
+    let mut active = vec![0];
+    let (column, row) = cell_of(points[0]);
+    grid[row * columns + column] = Some(0);
+
+    while !active.is_empty() && points.len() < max_points {
+        let slot = rng.gen_range(0..active.len());
+        let center = points[active[slot]];
+
+        let far_enough = |candidate: Vec2, points: &[Vec2], grid: &[Option<usize>]| {
+            let (column, row) = cell_of(candidate);
+            (row.saturating_sub(2)..(row + 3).min(rows)).all(|row| {
+                (column.saturating_sub(2)..(column + 3).min(columns)).all(|column| {
+                    grid[row * columns + column]
+                        .is_none_or(|index| points[index].distance(candidate) >= radius)
+                })
+            })
+        };
+
+        // Candidates in the ring between one and two radii around a point
+        // that is still looking for neighbours.
+        let found = (0..ATTEMPTS)
+            .map(|_| {
+                let angle = rng.gen::<f32>() * TAU;
+                let distance = radius * (1.0 + rng.gen::<f32>());
+                center + Vec2::from_angle(angle) * distance
+            })
+            .find(|&candidate| {
+                candidate.cmpge(Vec2::ZERO).all()
+                    && candidate.cmplt(size).all()
+                    && far_enough(candidate, &points, &grid)
+            });
+
+        match found {
+            Some(point) => {
+                let (column, row) = cell_of(point);
+                grid[row * columns + column] = Some(points.len());
+                active.push(points.len());
+                points.push(point);
+            }
+            None => {
+                active.swap_remove(slot);
+            }
+        }
+    }
+
+    points
+}
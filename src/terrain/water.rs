@@ -0,0 +1,404 @@
+use std::sync::Arc;
+
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    render::{
+        mesh::{Indices, MeshVertexAttribute, MeshVertexBufferLayout},
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup, PrimitiveTopology, RenderPipelineDescriptor, ShaderRef,
+            SpecializedMeshPipelineError, VertexFormat,
+        },
+    },
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::spectator::components::SpectatorCamera;
+
+use super::{
+    generation::{HeightSource, LakeMap, SculptLayer},
+    planet::PlanetSettings,
+    resources::{Terrain, TerrainMode, TerrainSettings},
+};
+
+/// How far below the sea level the terrain is at each water vertex, negative
+/// where the ground is above it.
+pub const ATTRIBUTE_WATER_DEPTH: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_WaterDepth", 988540920, VertexFormat::Float32);
+
+/// A flat sheet of concentric rings around the camera, or a cap of the sphere
+/// [`TerrainSettings::sea_level`] above the planet's surface in planet mode.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WaterSettings {
+    pub enabled: bool,
+    /// Quad size of the innermost ring, every following ring doubles it.
+    pub cell_size: f32,
+    /// Quads along each side of a ring, rounded to a multiple of four.
+    pub cells: u32,
+    pub rings: u32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cell_size: 4.0,
+            cells: 32,
+            rings: 10,
+        }
+    }
+}
+
+/// Colours water by how deep the terrain below it is, fading out towards the
+/// shoreline.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, PartialEq)]
+pub struct WaterMaterial {
+    /// Colour and opacity where the water is shallow.
+    #[uniform(0)]
+    pub shallow_color: Vec4,
+    #[uniform(0)]
+    pub deep_color: Vec4,
+    /// Depth at which the water reaches `deep_color`.
+    #[uniform(0)]
+    pub depth_range: f32,
+    /// Depth over which the water fades in from the shoreline.
+    #[uniform(0)]
+    pub shore_fade: f32,
+}
+
+impl Default for WaterMaterial {
+    fn default() -> Self {
+        Self {
+            shallow_color: Vec4::new(0.10, 0.45, 0.50, 0.45),
+            deep_color: Vec4::new(0.02, 0.10, 0.25, 0.92),
+            depth_range: 80.0,
+            shore_fade: 1.5,
+        }
+    }
+}
+
+impl Material for WaterMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/water/vertex.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water/fragment.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            ATTRIBUTE_WATER_DEPTH.at_shader_location(2),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+
+        Ok(())
+    }
+}
+
+/// What a water mesh was built for, to tell when it needs rebuilding.
+#[derive(Clone)]
+struct WaterKey {
+    anchor: Vec3,
+    sea_level: f32,
+    planet: Option<PlanetSettings>,
+    settings: WaterSettings,
+    source: Arc<dyn HeightSource>,
+    sculpt: Arc<SculptLayer>,
+    lakes: Arc<LakeMap>,
+}
+
+impl WaterKey {
+    fn matches(&self, other: &WaterKey, step: f32) -> bool {
+        self.anchor.distance_squared(other.anchor) < step * step
+            && self.sea_level == other.sea_level
+            && self.planet.map(|planet| planet.radius) == other.planet.map(|planet| planet.radius)
+            && self.settings == other.settings
+            && Arc::ptr_eq(&self.source, &other.source)
+            && Arc::ptr_eq(&self.sculpt, &other.sculpt)
+            && Arc::ptr_eq(&self.lakes, &other.lakes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaterBody {
+    /// The sea around the camera.
+    Ocean,
+    /// Every lake of [`Terrain::lakes`], on the flat world only.
+    Lakes,
+}
+
+#[derive(Component)]
+pub struct WaterSurface {
+    pub body: WaterBody,
+    built: Option<WaterKey>,
+    building: Option<(Task<Mesh>, WaterKey)>,
+}
+
+impl WaterSurface {
+    fn new(body: WaterBody) -> Self {
+        Self {
+            body,
+            built: None,
+            building: None,
+        }
+    }
+}
+
+pub fn spawn_water(mut commands: Commands, mut materials: ResMut<Assets<WaterMaterial>>) {
+    let material = materials.add(WaterMaterial::default());
+
+    for body in [WaterBody::Ocean, WaterBody::Lakes] {
+        commands.spawn((
+            SpatialBundle::default(),
+            material.clone(),
+            WaterSurface::new(body),
+        ));
+    }
+}
+
+/// Rebuilds the ocean around the camera once it has moved a few quads away
+/// from where the current mesh was built, and either body of water when the
+/// terrain changed under it.
+pub fn update_water(
+    mut water: Query<(Entity, &mut WaterSurface, &mut Transform, &mut Visibility)>,
+    handles: Query<&Handle<Mesh>, With<WaterSurface>>,
+    camera: Query<&Transform, (With<SpectatorCamera>, Without<WaterSurface>)>,
+    terrain: Res<Terrain>,
+    settings: Res<TerrainSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut commands: Commands,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+
+    for (entity, mut surface, mut transform, mut visibility) in &mut water {
+        let shown = settings.water.enabled
+            && (surface.body == WaterBody::Ocean || settings.mode == TerrainMode::Flat);
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        if !shown {
+            continue;
+        }
+
+        if let Some((task, key)) = &mut surface.building {
+            let Some(built) = block_on(future::poll_once(task)) else {
+                continue;
+            };
+
+            transform.translation = key.anchor;
+            // Bevy only computes bounds for entities without any, and a
+            // rebuilt mesh can outgrow the previous one's.
+            let bounds = built.compute_aabb().unwrap_or_default();
+            match handles.get(entity).ok() {
+                Some(mesh) => {
+                    meshes.insert(mesh, built);
+                    commands.entity(entity).insert(bounds);
+                }
+                None => {
+                    commands.entity(entity).insert((meshes.add(built), bounds));
+                }
+            }
+
+            surface.built = Some(key.clone());
+            surface.building = None;
+        }
+
+        let planet = match settings.mode {
+            TerrainMode::Flat => None,
+            TerrainMode::Planet => Some(settings.planet),
+        };
+
+        // Flat sheets snap to a few inner quads, so the inner rings' vertices
+        // land on the same spots as the camera moves and depths don't shimmer.
+        let step = settings.water.cell_size * 4.0;
+        let anchor = match planet {
+            _ if surface.body == WaterBody::Lakes => Vec3::ZERO,
+            None => {
+                let snapped = (camera.translation.xz() / step).round() * step;
+                Vec3::new(snapped.x, settings.sea_level, snapped.y)
+            }
+            Some(planet) => {
+                let up = (camera.translation - planet.center()).normalize_or_zero();
+                planet.center() + up * (planet.radius + settings.sea_level)
+            }
+        };
+
+        let key = WaterKey {
+            anchor,
+            sea_level: settings.sea_level,
+            planet,
+            settings: settings.water,
+            source: terrain.height_source.clone(),
+            sculpt: terrain.sculpt.clone(),
+            lakes: terrain.lakes.clone(),
+        };
+
+        if surface
+            .built
+            .as_ref()
+            .is_some_and(|built| built.matches(&key, step))
+        {
+            continue;
+        }
+
+        let body = surface.body;
+        let task = AsyncComputeTaskPool::get().spawn({
+            let key = key.clone();
+            async move {
+                match body {
+                    WaterBody::Ocean => generate_water_mesh(&key),
+                    WaterBody::Lakes => generate_lake_mesh(&key),
+                }
+            }
+        });
+        surface.building = Some((task, key));
+    }
+}
+
+fn generate_water_mesh(key: &WaterKey) -> Mesh {
+    let settings = &key.settings;
+    let cells = (settings.cells / 4).max(1) as i32 * 4;
+    let half = cells / 2;
+    let width = cells + 1;
+
+    // Tangent frame at the anchor for planet mode, the sheet is projected from
+    // it onto the sphere.
+    let (up, tangent, bitangent) = match key.planet {
+        None => (Vec3::Y, Vec3::X, Vec3::Z),
+        Some(planet) => {
+            let up = (key.anchor - planet.center()).normalize();
+            let tangent = up.any_orthonormal_vector();
+            (up, tangent, up.cross(tangent))
+        }
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut depths = Vec::new();
+    let mut indices = Vec::new();
+
+    for ring in 0..settings.rings.max(1) {
+        let cell = settings.cell_size * 2f32.powi(ring as i32);
+        let first = positions.len() as u32;
+
+        for i in 0..width {
+            for j in 0..width {
+                let offset = Vec2::new((i - half) as f32, (j - half) as f32) * cell;
+
+                let (position, normal, ground) = match key.planet {
+                    None => {
+                        let (x, z) = (key.anchor.x + offset.x, key.anchor.z + offset.y);
+                        let ground =
+                            key.source.height(x as f64, z as f64) as f32 + key.sculpt.height(x, z);
+                        (Vec3::new(offset.x, 0.0, offset.y), Vec3::Y, ground)
+                    }
+                    Some(planet) => {
+                        let radius = planet.radius + key.sea_level;
+                        let direction =
+                            (up * radius + tangent * offset.x + bitangent * offset.y).normalize();
+                        let surface = (direction * planet.radius).as_dvec3();
+                        let ground = key.source.height_3d(surface.x, surface.y, surface.z) as f32;
+                        let position = planet.center() + direction * radius - key.anchor;
+                        (position, direction, ground)
+                    }
+                };
+
+                positions.push(position.to_array());
+                normals.push(normal.to_array());
+                depths.push(key.sea_level - ground);
+            }
+        }
+
+        for i in 0..cells {
+            for j in 0..cells {
+                // Leave a hole where the previous ring is.
+                let inner = |c: i32| (half / 2..half + half / 2).contains(&c);
+                if ring > 0 && inner(i) && inner(j) {
+                    continue;
+                }
+
+                let index = first + (i * width + j) as u32;
+                let next_index = index + 1;
+                let bottom_index = index + width as u32;
+                let next_bottom_index = bottom_index + 1;
+
+                indices.extend([index, next_index, bottom_index]);
+                indices.extend([next_index, next_bottom_index, bottom_index]);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(ATTRIBUTE_WATER_DEPTH, depths);
+    mesh.insert_indices(Indices::U32(indices));
+
+    mesh
+}
+
+/// One quad per lake cell at its lake's level, in world space and wound like
+/// the ocean. Lakes under the sea are left to the ocean.
+fn generate_lake_mesh(key: &WaterKey) -> Mesh {
+    let mut positions = Vec::new();
+    let mut depths = Vec::new();
+    let mut indices = Vec::new();
+
+    key.lakes.for_each_cell(|cell, lake| {
+        if lake.level <= key.sea_level {
+            return;
+        }
+
+        let first = positions.len() as u32;
+        for corner in [
+            cell.min,
+            Vec2::new(cell.min.x, cell.max.y),
+            Vec2::new(cell.max.x, cell.min.y),
+            cell.max,
+        ] {
+            let ground = key.source.height(corner.x as f64, corner.y as f64) as f32
+                + key.sculpt.height(corner.x, corner.y);
+            positions.push([corner.x, lake.level, corner.y]);
+            depths.push(lake.level - ground);
+        }
+
+        indices.extend([first, first + 1, first + 2]);
+        indices.extend([first + 1, first + 3, first + 2]);
+    });
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(ATTRIBUTE_WATER_DEPTH, depths);
+    mesh.insert_indices(Indices::U32(indices));
+
+    mesh
+}